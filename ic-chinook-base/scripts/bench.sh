@@ -1,51 +1,43 @@
 #!/bin/bash
 
+# Measures the lookups with a cold cache: `close_database` drops the connection and its page cache,
+# and a query call discards all its heap changes, so every query call opens the database again.
+# A second call would not run warm, see the `bench_cold_warm_*` canbench benches in `src/cache.rs` for the warm runs.
+
 set -e
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT COUNT(*) FROM customers")'
-# 2nd run
+# cold run
 dfx canister call chinook_base query '("SELECT COUNT(*) FROM customers")'
 
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE customerid=900000")'
-# 2nd run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE customerid=900000")'
+# cold run
+dfx canister call chinook_base query_with_params '("SELECT firstname, lastname, email FROM customers WHERE customerid=?1", vec { variant { Integer = 900000 } })'
 
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE firstname = \"2912169customer_name2912169\"")'
-# 2nd run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE firstname = \"2912169customer_name2912169\"")'
+# cold run
+dfx canister call chinook_base query_with_params '("SELECT firstname, lastname, email FROM customers WHERE firstname = ?1", vec { variant { Text = "2912169customer_name2912169" } })'
 
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE firstname = \"1\"")'
-# 2nd run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE firstname = \"1\"")'
+# cold run
+dfx canister call chinook_base query_with_params '("SELECT firstname, lastname, email FROM customers WHERE firstname = ?1", vec { variant { Text = "1" } })'
 
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE customerid>900000 and customerid<900050")'
-# 2nd run
-dfx canister call chinook_base query '("SELECT firstname, lastname, email FROM customers WHERE customerid>900000 and customerid<900050")'
+# cold run
+dfx canister call chinook_base query_with_params '("SELECT firstname, lastname, email FROM customers WHERE customerid>?1 and customerid<?2", vec { variant { Integer = 900000 }; variant { Integer = 900050 } })'
 
-# reset DB cache
+# drop the connection and its page cache
 dfx canister call chinook_base close_database
-# 1st run
-dfx canister call chinook_base query '("SELECT count(*) FROM customers WHERE firstname>=\"1\" and firstname<\"2\"")'
-# 2nd run
-dfx canister call chinook_base query '("SELECT count(*) FROM customers WHERE firstname>=\"1\" and firstname<\"2\"")'
+# cold run
+dfx canister call chinook_base query_with_params '("SELECT count(*) FROM customers WHERE firstname>=?1 and firstname<?2", vec { variant { Text = "1" }; variant { Text = "2" } })'
 
 
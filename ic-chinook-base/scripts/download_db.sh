@@ -1,6 +1,49 @@
-#!/bin/sh
+#!/bin/bash
+
+# Download the canister database in chunks below the reply size limit
 
 set -e
 
-dfx canister call chinook_base download_database | sed -En 's/.*"([^"]*)".*/\1/p' | xxd -p -r > canister_base.db
+file=${1:-canister_base.db}
+chunk_size=2000000
+
+# decode the blob literal of the dfx output into raw bytes
+decode_blob() {
+    python3 -c '
+import re, sys
+text = re.search(r"blob \"(.*)\"", sys.stdin.read(), re.S).group(1)
+escapes = {"n": b"\n", "r": b"\r", "t": b"\t"}
+out = bytearray()
+for hex, esc, ch in re.findall(r"\\([0-9a-fA-F]{2})|\\(.)|(.)", text, re.S):
+    if hex:
+        out.append(int(hex, 16))
+    elif esc:
+        out += escapes.get(esc, esc.encode())
+    else:
+        out += ch.encode()
+sys.stdout.buffer.write(out)
+'
+}
+
+info=$(dfx canister call chinook_base begin_export)
+size=$(echo "$info" | sed -En 's/.*size = ([0-9_]+).*/\1/p' | tr -d '_')
+sha256=$(echo "$info" | sed -En 's/.*sha256 = "([0-9a-f]+)".*/\1/p')
+
+: > "$file"
+
+offset=0
+while [ $offset -lt $size ]; do
+    echo "downloading $offset of $size bytes"
+    dfx canister call --query chinook_base export_chunk "($offset, $chunk_size)" | decode_blob >> "$file"
+
+    offset=$(stat -c %s "$file")
+done
+
+dfx canister call chinook_base end_export
+
+if [ "$(sha256sum "$file" | cut -d ' ' -f 1)" != "$sha256" ]; then
+    echo "Error: checksum mismatch, expected $sha256." >&2
+    exit 1
+fi
 
+echo "downloaded $size bytes to $file"
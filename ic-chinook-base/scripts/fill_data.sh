@@ -11,37 +11,33 @@ dfx canister call chinook_base create_chinook_indices
 
 echo "adding customers"
 
+# the canister inserts the customers in batches of its own, one timer execution per batch
+OFFSET=1000000
+COUNT=1000000
 
-TOTAL=100000000000
-COUNTER=1000000
+job=`dfx canister call chinook_base start_add_customers "($OFFSET, $COUNT)"`
+echo "job: $job"
 
-PER=100000
+# extract the job id
+job_id=$(echo "$job" | grep -oP 'Ok = \K[0-9_]+' | tr -d '_')
 
-while [ $COUNTER -lt $TOTAL ];
+while true;
 do
+    sleep 5
 
     dfx canister deposit-cycles --all 200000000000
 
-    CUR=`expr $COUNTER + $PER`
-    echo "--- ${CUR} ---"
-
-    dfx canister call chinook_base add_customers "($COUNTER)"
+    status=`dfx canister call chinook_base job_status "($job_id)"`
+    echo "$status"
 
     db_size=`dfx canister call chinook_base get_db_size`
     echo "db_size: $db_size"
 
-    count=`dfx canister call chinook_base query '("select count(*) from customers")'`
-    echo "count: $count" 
-
-    # extract the number (between quotes)
-    num=$(echo "$count" | grep -oP '"\K[0-9]+(?=")')
-
-    # check if it's greater than 1000000
-    if (( num > 1000000 )); then
+    if echo "$status" | grep -q "finished = true"; then
+        if echo "$status" | grep -q "error = opt"; then
+            exit 1
+        fi
         exit 0
-    fi    
-
-    COUNTER=`expr $COUNTER + $PER`
+    fi
 
 done
-
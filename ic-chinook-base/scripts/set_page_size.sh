@@ -6,11 +6,18 @@ set -e
 db_size=`dfx canister call chinook_base get_db_size`
 echo "db_size: $db_size"
 
-dfx canister call chinook_base query '("pragma page_size=4096")'
+dfx canister call chinook_base admin_execute '("pragma page_size=4096")'
 
-dfx canister call chinook_base query '("pragma cache_size=10000")'
+dfx canister call chinook_base admin_execute '("pragma cache_size=10000")'
 
-dfx canister call chinook_base query '("vacuum")'
+# apply the page size, runs before `fill_data.sh` while the database is small enough to be vacuumed in one message
+report=`dfx canister call chinook_base vacuum`
+echo "$report"
+
+if ! echo "$report" | grep -q "Ok = record"; then
+    echo "Error: vacuum failed, the page size is not applied" >&2
+    exit 1
+fi
 
 db_size=`dfx canister call chinook_base get_db_size`
 echo "db_size: $db_size"
@@ -1,22 +1,36 @@
 #!/bin/bash
 
-# Upload the Chinook database into the canister
+# Upload the Chinook database into the canister in chunks below the 2MB ingress limit
 
 set -e
 
-file=chinook.db
+file=${1:-chinook.db}
+chunk_size=1000000
 
 if [[ ! -f "$file" ]]; then
     echo "Error: File '$file' does not exist, download it via './scripts/sample_download.sh'." >&2
     exit 1
 fi
 
-# prepare arguments
-echo -n '(blob "' > args.txt
-xxd -p -c 10000000 $file | tr -d '\n' | sed 's/\(..\)/\\\1/g' >>  args.txt
-echo -n '" )' >> args.txt
+size=$(stat -c %s "$file")
+sha256=$(sha256sum "$file" | cut -d ' ' -f 1)
 
-# upload DB
-dfx canister call chinook_base upload_database --argument-file args.txt
+dfx canister call chinook_base begin_upload "($size, \"$sha256\")"
 
+offset=0
+while [ $offset -lt $size ]; do
+    # prepare arguments
+    echo -n "($offset, blob \"" > args.txt
+    dd if="$file" bs=$chunk_size skip=$((offset / chunk_size)) count=1 status=none | xxd -p -c 10000000 | tr -d '\n' | sed 's/\(..\)/\\\1/g' >> args.txt
+    echo -n '" )' >> args.txt
 
+    echo "uploading $offset of $size bytes"
+    dfx canister call chinook_base upload_chunk --argument-file args.txt > /dev/null
+
+    offset=$((offset + chunk_size))
+done
+
+rm args.txt
+
+# verify the checksum and replace the database
+dfx canister call chinook_base finish_upload
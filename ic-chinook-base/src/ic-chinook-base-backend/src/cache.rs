@@ -0,0 +1,149 @@
+//! Cold and warm page cache benches of the `scripts/bench.sh` queries.
+//!
+//! Each query runs once on a freshly opened connection (cold, every page is read from the file)
+//! and once more on the same connection (warm, the pages are in the SQLite page cache).
+
+use ic_rusqlite::ffi;
+use ic_rusqlite::with_connection;
+
+/// Page cache counters of the connection since the previous call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    /// Pages found in the page cache
+    pub hits: i32,
+    /// Pages read from the database file
+    pub misses: i32,
+}
+
+/// Read and reset the page cache counters (`sqlite3_db_status`).
+pub fn take_cache_stats() -> CacheStats {
+    with_connection(|conn| {
+        let status = |op| {
+            let mut current = 0;
+            let mut highwater = 0;
+
+            // SAFETY: the handle belongs to the open connection, the counters are written into the local variables
+            let rc = unsafe {
+                ffi::sqlite3_db_status(conn.handle(), op, &mut current, &mut highwater, 1)
+            };
+            assert_eq!(rc, ffi::SQLITE_OK);
+
+            current
+        };
+
+        CacheStats {
+            hits: status(ffi::SQLITE_DBSTATUS_CACHE_HIT),
+            misses: status(ffi::SQLITE_DBSTATUS_CACHE_MISS),
+        }
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::{create_chinook_indices, execute, query_with_params, SqlValue};
+    use canbench_rs::{bench, bench_fn, bench_scope, BenchResult};
+
+    const COUNT: u64 = 100000u64;
+
+    // customers with the names of `add_customers`, without its long dummy texts in the other columns,
+    // so the rows are much smaller than the ones `scripts/bench.sh` runs on
+    fn fill_customers() {
+        create_chinook_indices();
+
+        execute(&format!(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < {COUNT})
+            INSERT INTO customers (firstname, lastname, email, address, city)
+            SELECT
+                id || 'customer_name' || id,
+                id || 'customer_last_name' || id,
+                id || 'customer@example.com',
+                'address ' || id,
+                'city ' || (id % 1000)
+            FROM n
+            "
+        ));
+    }
+
+    fn run(sql: &str, params: &[SqlValue]) -> CacheStats {
+        query_with_params(sql.to_string(), params.to_vec()).unwrap();
+
+        take_cache_stats()
+    }
+
+    /// Runs the query on a reopened connection (the `cold` scope), then once more (the `warm` scope).
+    fn bench_cold_warm(sql: &str, params: &[SqlValue]) -> BenchResult {
+        fill_customers();
+
+        let mut cold = CacheStats::default();
+        let mut warm = CacheStats::default();
+
+        let result = bench_fn(|| {
+            ic_rusqlite::close_connection();
+
+            {
+                let _p = bench_scope("cold");
+                cold = run(sql, params);
+            }
+            {
+                let _p = bench_scope("warm");
+                warm = run(sql, params);
+            }
+        });
+
+        // the first run reads the pages from the file, the second run finds all of them in the cache
+        assert!(cold.misses > 0);
+        assert_eq!(warm.misses, 0);
+        assert!(warm.hits > 0);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_count() -> BenchResult {
+        bench_cold_warm("SELECT COUNT(*) FROM customers", &[])
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_by_id() -> BenchResult {
+        bench_cold_warm(
+            "SELECT firstname, lastname, email FROM customers WHERE customerid=?1",
+            &[SqlValue::Integer(90000)],
+        )
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_by_first_name() -> BenchResult {
+        bench_cold_warm(
+            "SELECT firstname, lastname, email FROM customers WHERE firstname = ?1",
+            &[SqlValue::Text("29121customer_name29121".to_string())],
+        )
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_by_missing_first_name() -> BenchResult {
+        bench_cold_warm(
+            "SELECT firstname, lastname, email FROM customers WHERE firstname = ?1",
+            &[SqlValue::Text("1".to_string())],
+        )
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_id_range() -> BenchResult {
+        bench_cold_warm(
+            "SELECT firstname, lastname, email FROM customers WHERE customerid>?1 and customerid<?2",
+            &[SqlValue::Integer(90000), SqlValue::Integer(90050)],
+        )
+    }
+
+    #[bench(raw)]
+    fn bench_cold_warm_first_name_range() -> BenchResult {
+        bench_cold_warm(
+            "SELECT count(*) FROM customers WHERE firstname>=?1 and firstname<?2",
+            &[
+                SqlValue::Text("1".to_string()),
+                SqlValue::Text("2".to_string()),
+            ],
+        )
+    }
+}
@@ -0,0 +1,32 @@
+use std::fs::File;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Guard for controller-only endpoints
+pub fn caller_is_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::api::msg_caller()) {
+        Ok(())
+    } else {
+        Err("only controllers can call this method".to_string())
+    }
+}
+
+/// Guard for endpoints modifying the database, rejects calls while an export is in progress
+pub fn database_is_writable() -> Result<(), String> {
+    if crate::export::export_in_progress() {
+        Err("database is frozen for export, call end_export first".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Size and hex-encoded SHA-256 of a file
+pub fn sha256_file(path: &str) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    let size = std::io::copy(&mut file, &mut hasher)?;
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
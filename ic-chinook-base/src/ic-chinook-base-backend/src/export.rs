@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use crate::common::caller_is_controller;
+use crate::common::sha256_file;
+use crate::get_db_path;
+
+/// Largest chunk returned by `export_chunk`, keeps the reply below the message size limit
+const MAX_CHUNK_SIZE: u64 = 2000000;
+
+/// Description of the frozen database file
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportInfo {
+    /// File size in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+thread_local! {
+    static EXPORT: RefCell<Option<ExportInfo>> = const { RefCell::new(None) };
+}
+
+/// Whether the database is frozen by `begin_export`
+pub fn export_in_progress() -> bool {
+    EXPORT.with_borrow(|export| export.is_some())
+}
+
+/// Freeze the database and describe the file to download
+///
+/// Mutating endpoints are rejected until `end_export` is called.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn begin_export() -> Result<ExportInfo, String> {
+    if let Some(info) = EXPORT.with_borrow(|export| export.clone()) {
+        return Ok(info);
+    }
+
+    // flush and release the database file
+    ic_rusqlite::close_connection();
+
+    let (size, sha256) = sha256_file(&get_db_path()).map_err(|err| format!("{err:?}"))?;
+
+    let info = ExportInfo { size, sha256 };
+
+    EXPORT.with_borrow_mut(|export| *export = Some(info.clone()));
+
+    Ok(info)
+}
+
+/// Read up to `length` bytes (at most 2MB) of the frozen database starting at `offset`, an empty chunk marks the end of the file
+#[ic_cdk::query(guard = "caller_is_controller")]
+fn export_chunk(offset: u64, length: u64) -> Result<Vec<u8>, String> {
+    let info = EXPORT
+        .with_borrow(|export| export.clone())
+        .ok_or("no export in progress, call begin_export first".to_string())?;
+
+    if offset >= info.size {
+        return Ok(Vec::new());
+    }
+
+    let length = length.min(MAX_CHUNK_SIZE).min(info.size - offset);
+
+    let read = || -> std::io::Result<Vec<u8>> {
+        let mut file = File::open(get_db_path())?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut buffer = Vec::with_capacity(length as usize);
+        file.take(length).read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    };
+
+    read().map_err(|err| format!("{err:?}"))
+}
+
+/// Unfreeze the database
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn end_export() {
+    EXPORT.with_borrow_mut(|export| *export = None);
+}
+
+mod benches {
+    use super::*;
+    use crate::common::database_is_writable;
+    use crate::execute;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    use sha2::Digest;
+    use sha2::Sha256;
+
+    // small chunks, so the database is downloaded in many pieces
+    const TEST_CHUNK_SIZE: u64 = 64 * 1024;
+
+    #[bench(raw)]
+    fn bench_export_database_chunked() -> BenchResult {
+        execute(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < 100000)
+            INSERT INTO users (username, email) SELECT 'user' || id, 'user' || id || '@example.com' FROM n
+            ",
+        );
+
+        let mut downloaded = Vec::new();
+
+        let result = bench_fn(|| {
+            begin_export().unwrap();
+
+            loop {
+                let chunk = export_chunk(downloaded.len() as u64, TEST_CHUNK_SIZE).unwrap();
+                if chunk.is_empty() {
+                    break;
+                }
+                assert!(chunk.len() as u64 <= TEST_CHUNK_SIZE);
+                downloaded.extend_from_slice(&chunk);
+            }
+        });
+
+        let info = EXPORT.with_borrow(|export| export.clone()).unwrap();
+
+        // writes are rejected while the export is in progress
+        assert!(database_is_writable().is_err());
+
+        // the download is byte-identical to the frozen database
+        assert_eq!(downloaded.len() as u64, info.size);
+        assert_eq!(downloaded, std::fs::read(get_db_path()).unwrap());
+        assert_eq!(hex::encode(Sha256::digest(&downloaded)), info.sha256);
+
+        // larger requests are capped
+        assert_eq!(
+            export_chunk(0, u64::MAX).unwrap().len() as u64,
+            MAX_CHUNK_SIZE.min(info.size)
+        );
+
+        end_export();
+        assert!(database_is_writable().is_ok());
+
+        result
+    }
+}
@@ -0,0 +1,283 @@
+use std::time::Duration;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+use ic_rusqlite::Connection;
+use ic_rusqlite::OptionalExtension;
+
+use crate::common::database_is_writable;
+use crate::insert_customer;
+use crate::INSERT_CUSTOMER;
+
+/// Instructions one batch may spend on inserts, well below the 40B limit of a message,
+/// so the commit and the progress update always fit
+const BATCH_INSTRUCTIONS: u64 = 10_000_000_000;
+
+/// Delay before retrying a batch while the database is frozen for export
+const FROZEN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Progress of a `start_add_customers` job
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct JobStatus {
+    pub job_id: u64,
+    pub offset: u64,
+    pub count: u64,
+    /// Customers inserted so far
+    pub done: u64,
+    /// Committed batches so far
+    pub batches: u64,
+    /// Instructions spent on all batches
+    pub instructions: u64,
+    pub finished: bool,
+    /// The error stopping the job, if any
+    pub error: Option<String>,
+}
+
+// The job table lives in the database, so the progress is committed together with the inserted rows
+// and survives upgrades.
+fn create_job_table(conn: &Connection) -> ic_rusqlite::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS bulk_jobs (
+            job_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            start_offset INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            done INTEGER NOT NULL DEFAULT 0,
+            batches INTEGER NOT NULL DEFAULT 0,
+            instructions INTEGER NOT NULL DEFAULT 0,
+            finished INTEGER NOT NULL DEFAULT 0,
+            error TEXT
+        )
+        ",
+    )
+}
+
+fn job_table_exists(conn: &Connection) -> ic_rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table' AND name = 'bulk_jobs'",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn load_job(conn: &Connection, job_id: u64) -> ic_rusqlite::Result<Option<JobStatus>> {
+    conn.query_row(
+        "SELECT start_offset, count, done, batches, instructions, finished, error
+         FROM bulk_jobs WHERE job_id = ?1",
+        [job_id],
+        |row| {
+            Ok(JobStatus {
+                job_id,
+                offset: row.get(0)?,
+                count: row.get(1)?,
+                done: row.get(2)?,
+                batches: row.get(3)?,
+                instructions: row.get(4)?,
+                finished: row.get(5)?,
+                error: row.get(6)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Insert up to `count` customers starting at `offset` until the instructions counted from `start` exceed the batch budget,
+/// returns the number of inserted customers
+pub fn insert_customers(
+    conn: &Connection,
+    offset: u64,
+    count: u64,
+    start: u64,
+) -> ic_rusqlite::Result<u64> {
+    let mut stmt = conn.prepare_cached(INSERT_CUSTOMER)?;
+
+    let mut done = 0;
+
+    while done < count && ic_cdk::api::instruction_counter() - start < BATCH_INSTRUCTIONS {
+        insert_customer(&mut stmt, offset + done)?;
+        done += 1;
+    }
+
+    Ok(done)
+}
+
+fn create_job(offset: u64, count: u64) -> ic_rusqlite::Result<u64> {
+    with_connection(|conn| {
+        create_job_table(&conn)?;
+
+        conn.execute(
+            "INSERT INTO bulk_jobs (start_offset, count) VALUES (?1, ?2)",
+            ic_rusqlite::params![offset, count],
+        )?;
+
+        Ok(conn.last_insert_rowid() as u64)
+    })
+}
+
+fn schedule(job_id: u64, delay: Duration) {
+    ic_cdk_timers::set_timer(delay, move || run_job(job_id));
+}
+
+// timer callback: run one batch and reschedule until the job is finished
+fn run_job(job_id: u64) {
+    if database_is_writable().is_err() {
+        schedule(job_id, FROZEN_RETRY_DELAY);
+        return;
+    }
+
+    match run_batch(job_id) {
+        Ok(true) => {}
+        Ok(false) => schedule(job_id, Duration::ZERO),
+        Err(err) => {
+            // the failed batch was rolled back, record the error and stop the job
+            let recorded = with_connection(|conn| {
+                conn.execute(
+                    "UPDATE bulk_jobs SET finished = 1, error = ?2 WHERE job_id = ?1",
+                    ic_rusqlite::params![job_id, err],
+                )
+            });
+
+            // a trap would only roll back this timer execution, the job stops either way
+            if let Err(err) = recorded {
+                ic_cdk::eprintln!("failed to record the error of job {job_id}: {err:?}");
+            }
+        }
+    }
+}
+
+/// Insert customers of the job until the batch budget is used up, returns `true` once the job is finished
+///
+/// The customers and the job progress are committed in one transaction.
+fn run_batch(job_id: u64) -> Result<bool, String> {
+    let start = ic_cdk::api::instruction_counter();
+
+    with_connection(|mut conn| {
+        let tx = conn.transaction().map_err(|err| format!("{err:?}"))?;
+
+        let job = load_job(&tx, job_id)
+            .map_err(|err| format!("{err:?}"))?
+            .ok_or(format!("job {job_id} not found"))?;
+
+        if job.finished {
+            return Ok(true);
+        }
+
+        let done = job.done
+            + insert_customers(&tx, job.offset + job.done, job.count - job.done, start)
+                .map_err(|err| format!("{err:?}"))?;
+
+        let finished = done >= job.count;
+
+        tx.execute(
+            "UPDATE bulk_jobs SET done = ?2, batches = batches + 1, instructions = instructions + ?3, finished = ?4
+             WHERE job_id = ?1",
+            ic_rusqlite::params![
+                job_id,
+                done,
+                ic_cdk::api::instruction_counter() - start,
+                finished
+            ],
+        )
+        .map_err(|err| format!("{err:?}"))?;
+
+        tx.commit().map_err(|err| format!("{err:?}"))?;
+
+        Ok(finished)
+    })
+}
+
+/// Continue the unfinished jobs, the timers are lost on upgrade
+pub fn resume_jobs() {
+    // an unreadable job is skipped, trapping here would fail the whole upgrade
+    let jobs = with_connection(|conn| {
+        if !job_table_exists(&conn)? {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = conn.prepare("SELECT job_id FROM bulk_jobs WHERE finished = 0")?;
+        let ids = stmt.query_map([], |row| row.get::<_, u64>(0))?;
+
+        Ok::<_, ic_rusqlite::Error>(ids.collect::<Vec<_>>())
+    });
+
+    let jobs = match jobs {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            ic_cdk::eprintln!("failed to read the unfinished jobs: {err:?}");
+            return;
+        }
+    };
+
+    for job in jobs {
+        match job {
+            Ok(job_id) => schedule(job_id, Duration::ZERO),
+            Err(err) => ic_cdk::eprintln!("failed to read an unfinished job: {err:?}"),
+        }
+    }
+}
+
+/// Start inserting `count` customers starting at `offset` in the background, returns the job id
+///
+/// Each batch runs in its own timer execution, see `job_status` for the progress.
+#[ic_cdk::update(guard = "database_is_writable")]
+fn start_add_customers(offset: u64, count: u64) -> Result<u64, String> {
+    let job_id = create_job(offset, count).map_err(|err| format!("{err:?}"))?;
+
+    schedule(job_id, Duration::ZERO);
+
+    Ok(job_id)
+}
+
+#[ic_cdk::query]
+fn job_status(job_id: u64) -> Result<JobStatus, String> {
+    with_connection(|conn| {
+        if !job_table_exists(&conn)? {
+            return Ok(None);
+        }
+
+        load_job(&conn, job_id)
+    })
+    .map_err(|err| format!("{err:?}"))?
+    .ok_or(format!("job {job_id} not found"))
+}
+
+mod benches {
+    use super::*;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000;
+
+    fn count_customers() -> u64 {
+        with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM customers", [], |row| row.get(0))
+        })
+        .unwrap()
+    }
+
+    #[bench(raw)]
+    fn bench_add_customers_job() -> BenchResult {
+        // no job table yet, the status query does not create it
+        assert!(job_status(1).is_err());
+        assert!(!with_connection(|conn| job_table_exists(&conn)).unwrap());
+
+        // the jobs are created without timers, the batches run directly
+        let job_id = create_job(0, COUNT).unwrap();
+
+        let result = bench_fn(|| while !run_batch(job_id).unwrap() {});
+
+        let status = job_status(job_id).unwrap();
+
+        assert!(status.finished);
+        assert_eq!(status.error, None);
+        assert_eq!(status.done, COUNT);
+        assert_eq!(count_customers(), COUNT);
+
+        // a finished job is not continued
+        assert!(run_batch(job_id).unwrap());
+        assert_eq!(count_customers(), COUNT);
+
+        result
+    }
+}
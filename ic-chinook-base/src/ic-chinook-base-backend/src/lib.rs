@@ -1,44 +1,147 @@
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::Read;
-use std::io::Write;
+
+use candid::CandidType;
+use candid::Deserialize;
 
 use ic_cdk::export_candid;
 
 use ic_rusqlite::close_connection;
+use ic_rusqlite::params_from_iter;
+use ic_rusqlite::types::ToSqlOutput;
 use ic_rusqlite::types::Type;
+use ic_rusqlite::types::ValueRef;
 use ic_rusqlite::with_connection;
+use ic_rusqlite::Params;
+use ic_rusqlite::Statement;
+use ic_rusqlite::ToSql;
+
+mod cache;
+mod common;
+mod export;
+mod jobs;
+mod maintenance;
+mod schema;
+mod stats;
+mod upload;
+
+use common::caller_is_controller;
+use common::database_is_writable;
+
+// types of the module endpoints, in scope for `export_candid!`
+use export::ExportInfo;
+use jobs::JobStatus;
+use maintenance::MaintenanceReport;
+use schema::Schema;
+use stats::DbStats;
+use upload::UploadStatus;
+
+/// A single SQLite value used as a bound parameter.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
 
-#[ic_cdk::update]
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> ic_rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            SqlValue::Null => ValueRef::Null,
+            SqlValue::Integer(v) => ValueRef::Integer(*v),
+            SqlValue::Real(v) => ValueRef::Real(*v),
+            SqlValue::Text(v) => ValueRef::Text(v.as_bytes()),
+            SqlValue::Blob(v) => ValueRef::Blob(v),
+        }))
+    }
+}
+
+fn collect_rows(
+    stmt: &mut Statement,
+    params: impl Params,
+) -> ic_rusqlite::Result<Vec<Vec<Option<String>>>> {
+    let cnt = stmt.column_count();
+
+    // create rows iterator
+    let rows_iter = stmt.query_map(params, |row| {
+        let mut vec: Vec<Option<String>> = Vec::new();
+
+        for idx in 0..cnt {
+            match row.get_ref(idx)?.data_type() {
+                Type::Null => vec.push(None),
+                Type::Integer => vec.push(Some(row.get::<_, i64>(idx)?.to_string())),
+                Type::Real => vec.push(Some(row.get::<_, f64>(idx)?.to_string())),
+                Type::Text => vec.push(Some(row.get(idx)?)),
+                Type::Blob => vec.push(Some(hex::encode(row.get::<_, Vec<u8>>(idx)?))),
+            }
+        }
+
+        Ok(vec)
+    })?;
+
+    rows_iter.collect()
+}
+
+fn check_read_only(stmt: &Statement, sql: &str) -> Result<(), String> {
+    if !stmt.readonly() {
+        return Err(format!(
+            "only read-only statements are allowed, use admin_execute for: {sql}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Run a statement that does not modify the database, other statements are rejected
+fn read_only_query(sql: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare(sql).map_err(|err| format!("{err:?}"))?;
+
+        check_read_only(&stmt, sql)?;
+
+        collect_rows(&mut stmt, []).map_err(|err| format!("{err:?}"))
+    })
+}
+
+/// Run a read-only statement as a query call, use `admin_execute` for pragmas, `vacuum` or other writes
+#[ic_cdk::query]
 fn query(sql: String) -> Vec<Vec<Option<String>>> {
     let start = ic_instruction_counter();
 
+    let res = read_only_query(&sql).unwrap_or_else(|err| ic_cdk::trap(err));
+
+    let end = ic_instruction_counter();
+
+    println!("Query {}\n execution time: {}", sql, end - start);
+
+    res
+}
+
+/// Execute statements modifying the database, e.g. `pragma page_size=4096` or `vacuum`
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn admin_execute(sql: String) -> Result<(), String> {
+    with_connection(|conn| conn.execute_batch(&sql)).map_err(|err| format!("{err:?}"))
+}
+
+/// Run a read-only statement with bound parameters as a query call, use `execute_with_params` for writes
+#[ic_cdk::query]
+fn query_with_params(
+    sql: String,
+    params: Vec<SqlValue>,
+) -> Result<Vec<Vec<Option<String>>>, String> {
+    let start = ic_instruction_counter();
+
     let res = with_connection(|conn| {
-        let mut stmt = conn.prepare(&sql).unwrap();
-        let cnt = stmt.column_count();
+        let mut stmt = conn
+            .prepare_cached(&sql)
+            .map_err(|err| format!("{err:?}"))?;
 
-        // create rows iterator
-        let rows_iter = stmt
-            .query_map([], |row| {
-                let mut vec: Vec<Option<String>> = Vec::new();
-
-                for idx in 0..cnt {
-                    let v = row.get_ref_unwrap(idx);
-                    match v.data_type() {
-                        Type::Null => vec.push(None),
-                        Type::Integer => vec.push(Some(v.as_i64().unwrap().to_string())),
-                        Type::Real => vec.push(Some(v.as_f64().unwrap().to_string())),
-                        Type::Text => vec.push(Some(v.as_str().unwrap().parse().unwrap())),
-                        Type::Blob => vec.push(Some(hex::encode(v.as_blob().unwrap()))),
-                    }
-                }
-
-                Ok(vec)
-            })
-            .unwrap();
+        check_read_only(&stmt, &sql)?;
 
-        let res: Vec<Vec<Option<String>>> = rows_iter.filter_map(Result::ok).collect();
-        res
+        collect_rows(&mut stmt, params_from_iter(params.iter())).map_err(|err| format!("{err:?}"))
     });
 
     let end = ic_instruction_counter();
@@ -48,26 +151,54 @@ fn query(sql: String) -> Vec<Vec<Option<String>>> {
     res
 }
 
-fn get_db_path() -> String {
-    let config = ic_rusqlite::get_connection_config();
+/// Execute a statement with bound parameters, returns the number of changed rows
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn execute_with_params(sql: String, params: Vec<SqlValue>) -> Result<u64, String> {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare_cached(&sql)?;
 
-    config.db_file_name
+        stmt.execute(params_from_iter(params.iter()))
+    })
+    .map(|changed| changed as u64)
+    .map_err(|err| format!("{err:?}"))
 }
 
-#[ic_cdk::update]
-pub fn upload_database(db: Vec<u8>) {
-    close_connection();
+/// One row of `EXPLAIN QUERY PLAN`, the steps form a tree through `parent`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct PlanStep {
+    id: i64,
+    /// Id of the enclosing step, 0 for the top level steps
+    parent: i64,
+    /// E.g. `SEARCH customers USING INDEX idx_customers_first_name (FirstName=?)`
+    detail: String,
+}
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(false)
-        .truncate(true)
-        .open(get_db_path())
-        .unwrap();
+/// Show how SQLite executes `sql` without running it, parameters (`?1`) are left unbound
+#[ic_cdk::query]
+fn explain(sql: String) -> Result<Vec<PlanStep>, String> {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}"))?;
+
+        let steps = stmt.query_map([], |row| {
+            Ok(PlanStep {
+                id: row.get(0)?,
+                parent: row.get(1)?,
+                detail: row.get(3)?,
+            })
+        })?;
+
+        steps.collect::<ic_rusqlite::Result<Vec<_>>>()
+    })
+    .map_err(|err| format!("{err:?}"))
+}
+
+fn get_db_path() -> String {
+    let config = ic_rusqlite::get_connection_config();
 
-    file.write_all(&db).unwrap();
+    config.db_file_name
 }
 
+/// Download the whole database in one reply, use `begin_export` and `export_chunk` for files above 2MB
 #[ic_cdk::query]
 pub fn download_database() -> Vec<u8> {
     close_connection();
@@ -83,7 +214,7 @@ pub fn download_database() -> Vec<u8> {
     buf
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "caller_is_controller")]
 pub fn close_database() {
     close_connection();
 }
@@ -119,7 +250,7 @@ fn execute(sql: &str) {
     })
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
 pub fn execute_batch(sql: &str) {
     with_connection(|conn| {
         conn.execute_batch(sql).unwrap();
@@ -149,6 +280,27 @@ fn create_tables() {
             )
         ",
     );
+
+    // the customers table of the Chinook sample database, so a canister without the uploaded sample can add customers
+    execute(
+        "
+            CREATE TABLE IF NOT EXISTS customers (
+                CustomerId INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                FirstName NVARCHAR(40) NOT NULL,
+                LastName NVARCHAR(20) NOT NULL,
+                Company NVARCHAR(80),
+                Address NVARCHAR(70),
+                City NVARCHAR(40),
+                State NVARCHAR(40),
+                Country NVARCHAR(40),
+                PostalCode NVARCHAR(10),
+                Phone NVARCHAR(24),
+                Fax NVARCHAR(24),
+                Email NVARCHAR(60) NOT NULL,
+                SupportRepId INTEGER
+            )
+        ",
+    );
 }
 
 fn create_indices() {
@@ -156,7 +308,7 @@ fn create_indices() {
     execute("CREATE INDEX IF NOT EXISTS idx_orders_user_id ON orders(user_id);");
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "database_is_writable")]
 fn create_chinook_indices() {
     execute("CREATE INDEX IF NOT EXISTS idx_customers_first_name ON customers(firstname);");
     execute("CREATE INDEX IF NOT EXISTS idx_customers_last_name ON customers(lastname);");
@@ -169,7 +321,13 @@ fn init() {
     create_indices();
 }
 
-#[ic_cdk::update]
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    jobs::resume_jobs();
+}
+
+/// Names of the tables, see `get_schema` for their columns, indexes and foreign keys
+#[ic_cdk::query]
 fn get_tables() -> Vec<String> {
     with_connection(|conn| {
         let mut stmt = conn
@@ -199,47 +357,36 @@ fn get_tables() -> Vec<String> {
 
 use ic_cdk::api::instruction_counter as ic_instruction_counter;
 
-#[ic_cdk::update]
+const INSERT_CUSTOMER: &str = "insert into customers (firstname, lastname, email, address, city, state, country, postalcode, phone, fax) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// Insert the customer number `n` (zero-based) using a prepared `INSERT_CUSTOMER` statement.
+fn insert_customer(stmt: &mut Statement, n: u64) -> ic_rusqlite::Result<usize> {
+    let id = n + 1;
+    let name = format!("{id}customer_name{id}");
+    let last_name = format!(
+        "{}customer_last_name{}",
+        (id * 120301070105014129u64) % u64::MAX,
+        (id * 120301070105014129u64) % u64::MAX
+    );
+    let email = format!("{id}customer@example.com");
+    let dummy = "is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum";
+
+    stmt.execute(ic_rusqlite::params![
+        name, last_name, email, dummy, dummy, dummy, dummy, dummy, dummy, dummy
+    ])
+}
+
+/// Insert customers starting at `offset` until the batch instruction budget is used up, returns the instructions spent
+///
+/// Use `start_add_customers` to insert any number of customers in the background.
+#[ic_cdk::update(guard = "database_is_writable")]
 fn add_customers(offset: u64) -> u64 {
     let start = ic_instruction_counter();
 
     with_connection(|mut conn| {
         let tx = conn.transaction().unwrap();
 
-        let sql = String::from(
-            "insert into customers (firstname, lastname, email, address, city, state, country, postalcode, phone, fax) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        );
-
-        {
-            let mut stmt = tx.prepare_cached(&sql).unwrap();
-
-            let mut i = 0;
-
-            while i < 100000000 {
-                let id = offset + i + 1;
-                let name = format!("{id}customer_name{id}");
-                let last_name = format!(
-                    "{}customer_last_name{}",
-                    (id * 120301070105014129u64) % u64::MAX,
-                    (id * 120301070105014129u64) % u64::MAX
-                );
-                let email = format!("{id}customer@example.com");
-                let dummy = "is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsumis simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum";
-
-                stmt.execute(ic_rusqlite::params![
-                    name, last_name, email, dummy, dummy, dummy, dummy, dummy, dummy, dummy
-                ])
-                .expect("insert of a user failed!");
-
-                i += 1;
-
-                //
-                let end = ic_instruction_counter();
-                if end - start > 15000000000 || i > 25000 {
-                    break;
-                }
-            }
-        }
+        jobs::insert_customers(&tx, offset, u64::MAX, start).expect("insert of a user failed!");
 
         tx.commit().expect("COMMIT USER INSERTION FAILED!");
     });
@@ -314,4 +461,136 @@ fn add_orders(offset: usize, count: usize, id_mod: usize) -> Result {
 
 */
 
+mod benches {
+    use super::*;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    #[bench(raw)]
+    fn bench_query_is_read_only() -> BenchResult {
+        admin_execute(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < 1000)
+            INSERT INTO users (username, email) SELECT 'user' || id, 'user' || id || '@example.com' FROM n
+            "
+            .to_string(),
+        )
+        .unwrap();
+
+        let mut res = vec![];
+
+        let result = bench_fn(|| {
+            res = query("SELECT COUNT(*) FROM users".to_string());
+        });
+
+        assert_eq!(res[0][0].as_deref(), Some("1000"));
+
+        // statements modifying the database are rejected
+        assert!(read_only_query("DELETE FROM users").is_err());
+        assert!(read_only_query("INSERT INTO users (username, email) VALUES ('a', 'b')").is_err());
+        assert!(read_only_query("CREATE TABLE t (x)").is_err());
+        assert!(read_only_query("SELECT COUNT(*) FROM users").is_ok());
+
+        // and go through `admin_execute` instead
+        admin_execute("DELETE FROM users WHERE user_id > 10".to_string()).unwrap();
+        assert_eq!(
+            query("SELECT COUNT(*) FROM users".to_string())[0][0].as_deref(),
+            Some("10")
+        );
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_query_with_params() -> BenchResult {
+        execute_with_params(
+            "INSERT INTO users (username, email) VALUES (?1, ?2)".to_string(),
+            vec![
+                SqlValue::Text("user1".to_string()),
+                SqlValue::Text("user1@example.com".to_string()),
+            ],
+        )
+        .unwrap();
+
+        let mut res = vec![];
+
+        let result = bench_fn(|| {
+            res = query_with_params(
+                "SELECT username, email FROM users WHERE user_id = ?1".to_string(),
+                vec![SqlValue::Integer(1)],
+            )
+            .unwrap();
+        });
+
+        assert_eq!(res[0][0].as_deref(), Some("user1"));
+
+        // SQL errors are returned instead of trapping
+        assert!(query_with_params("SELEC 1".to_string(), vec![]).is_err());
+        // writes are rejected, they would be discarded at the end of the query call
+        assert!(query_with_params(
+            "DELETE FROM users WHERE user_id = ?1".to_string(),
+            vec![SqlValue::Integer(1)]
+        )
+        .is_err());
+        assert_eq!(
+            query_with_params("SELECT COUNT(*) FROM users".to_string(), vec![]).unwrap()[0][0]
+                .as_deref(),
+            Some("1")
+        );
+        assert!(execute_with_params("INSERT INTO missing VALUES (1)".to_string(), vec![]).is_err());
+
+        result
+    }
+
+    fn uses_index(plan: &[PlanStep], index: &str) -> bool {
+        plan.iter().any(|step| step.detail.contains(index))
+    }
+
+    // the lookups of `scripts/bench.sh`, without the index each of them scans all customers
+    #[bench(raw)]
+    fn bench_explain_customer_lookups() -> BenchResult {
+        create_chinook_indices();
+
+        let mut by_name = vec![];
+        let mut by_name_range = vec![];
+        let mut by_id = vec![];
+
+        let result = bench_fn(|| {
+            by_name = explain(
+                "SELECT firstname, lastname, email FROM customers WHERE firstname = ?1".to_string(),
+            )
+            .unwrap();
+            by_name_range = explain(
+                "SELECT count(*) FROM customers WHERE firstname>=?1 and firstname<?2".to_string(),
+            )
+            .unwrap();
+            by_id = explain(
+                "SELECT firstname, lastname, email FROM customers WHERE customerid=?1".to_string(),
+            )
+            .unwrap();
+        });
+
+        assert!(
+            uses_index(&by_name, "idx_customers_first_name"),
+            "{by_name:?}"
+        );
+        assert!(
+            uses_index(&by_name_range, "idx_customers_first_name"),
+            "{by_name_range:?}"
+        );
+        assert!(uses_index(&by_id, "INTEGER PRIMARY KEY"), "{by_id:?}");
+
+        // the check notices the regression
+        execute("DROP INDEX idx_customers_first_name");
+        let plan = explain(
+            "SELECT firstname, lastname, email FROM customers WHERE firstname = ?1".to_string(),
+        )
+        .unwrap();
+        assert!(!uses_index(&plan, "idx_customers_first_name"), "{plan:?}");
+
+        assert!(explain("SELECT * FROM missing_table".to_string()).is_err());
+
+        result
+    }
+}
+
 export_candid!();
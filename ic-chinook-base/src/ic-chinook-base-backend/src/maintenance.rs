@@ -0,0 +1,302 @@
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_cdk::api::instruction_counter;
+use ic_rusqlite::with_connection;
+
+use crate::common::caller_is_controller;
+use crate::common::database_is_writable;
+use crate::get_db_path;
+
+/// Instruction limit of an update call
+const MESSAGE_INSTRUCTION_LIMIT: u64 = 40_000_000_000;
+
+// Costs per byte of the database file, about twice the calibrated costs on the 100K users of the benches below:
+// the native costs relative to `CREATE INDEX` (6.3B instructions in the `sql-users-orders` benches)
+// plus 5 instructions per byte read and 14 per byte written by the file system (`fs-benchmarks`).
+// The benches check that the measured costs stay between a tenth of the estimate and the estimate.
+
+// VACUUM copies every row into a new file and rebuilds the indices.
+const VACUUM_PER_BYTE: u64 = 150;
+// ANALYZE scans every index.
+const ANALYZE_PER_BYTE: u64 = 25;
+// integrity_check reads every page and looks up each index entry.
+const INTEGRITY_CHECK_PER_BYTE: u64 = 50;
+// quick_check reads every page like integrity_check but skips the index lookups, natively about a third of its cost.
+pub(crate) const QUICK_CHECK_PER_BYTE: u64 = 25;
+// incremental_vacuum moves pages from the end of the file into the free pages, costs per freed byte.
+const INCREMENTAL_VACUUM_PER_BYTE: u64 = 15;
+// PRAGMA optimize samples a limited number of rows per index (`analysis_limit`), so its cost barely depends on the size.
+const OPTIMIZE_INSTRUCTIONS: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct PageCounts {
+    page_size: u64,
+    page_count: u64,
+    /// Unused pages, reclaimed by `vacuum` and `incremental_vacuum`
+    freelist_count: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MaintenanceReport {
+    /// Instructions the operation was expected to take
+    estimated_instructions: u64,
+    instructions: u64,
+    size_before: u64,
+    size_after: u64,
+    pages_before: PageCounts,
+    pages_after: PageCounts,
+    /// Rows returned by the pragma, e.g. the problems found by `integrity_check`
+    messages: Vec<String>,
+}
+
+fn page_counts() -> Result<PageCounts, String> {
+    with_connection(|conn| {
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0));
+
+        Ok(PageCounts {
+            page_size: pragma("page_size")?,
+            page_count: pragma("page_count")?,
+            freelist_count: pragma("freelist_count")?,
+        })
+    })
+    .map_err(|err: ic_rusqlite::Error| format!("{err:?}"))
+}
+
+/// Refuse an operation that would not finish within the message instruction limit
+pub(crate) fn check_budget(operation: &str, estimated: u64) -> Result<(), String> {
+    let available = MESSAGE_INSTRUCTION_LIMIT.saturating_sub(instruction_counter());
+
+    if estimated > available {
+        return Err(format!(
+            "{operation} is estimated to take {estimated} instructions, only {available} are left in this message"
+        ));
+    }
+
+    Ok(())
+}
+
+fn file_size() -> Result<u64, String> {
+    std::fs::metadata(get_db_path())
+        .map(|m| m.len())
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Run `sql` if its estimated cost fits into the message, and measure it
+fn run_maintenance(
+    operation: &str,
+    estimate: impl FnOnce(u64, &PageCounts) -> u64,
+    sql: &str,
+) -> Result<MaintenanceReport, String> {
+    let size_before = file_size()?;
+    let pages_before = page_counts()?;
+
+    let estimated_instructions = estimate(size_before, &pages_before);
+    check_budget(operation, estimated_instructions)?;
+
+    let start = instruction_counter();
+
+    // the connection stays open between calls, it keeps pending settings like a changed `page_size`
+    // or `auto_vacuum` for the next `VACUUM`
+    let messages = with_connection(|conn| {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<ic_rusqlite::Result<Vec<_>>>()
+    })
+    .map_err(|err| format!("{err:?}"))?;
+
+    let instructions = instruction_counter() - start;
+
+    Ok(MaintenanceReport {
+        estimated_instructions,
+        instructions,
+        size_before,
+        size_after: file_size()?,
+        pages_before,
+        pages_after: page_counts()?,
+        messages,
+    })
+}
+
+/// Rebuild the database file, reclaiming the free pages and applying a changed `page_size` or `auto_vacuum`
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn vacuum() -> Result<MaintenanceReport, String> {
+    run_maintenance("vacuum", |size, _| size * VACUUM_PER_BYTE, "VACUUM")
+}
+
+/// Move up to `pages` free pages to the end of the file and truncate it, 0 frees all of them
+///
+/// Only works with `auto_vacuum=INCREMENTAL`, which takes effect on an existing database after `vacuum`.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn incremental_vacuum(pages: u64) -> Result<MaintenanceReport, String> {
+    let auto_vacuum: i64 =
+        with_connection(|conn| conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0)))
+            .map_err(|err| format!("{err:?}"))?;
+
+    // 2 is INCREMENTAL
+    if auto_vacuum != 2 {
+        return Err(
+            "auto_vacuum is not INCREMENTAL, set it with `PRAGMA auto_vacuum=INCREMENTAL` and run vacuum first"
+                .to_string(),
+        );
+    }
+
+    run_maintenance(
+        "incremental_vacuum",
+        |_, counts| {
+            let freed = if pages == 0 {
+                counts.freelist_count
+            } else {
+                pages.min(counts.freelist_count)
+            };
+
+            freed * counts.page_size * INCREMENTAL_VACUUM_PER_BYTE
+        },
+        &format!("PRAGMA incremental_vacuum({pages})"),
+    )
+}
+
+/// Collect the index statistics used by the query planner into `sqlite_stat1`
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn analyze() -> Result<MaintenanceReport, String> {
+    run_maintenance("analyze", |size, _| size * ANALYZE_PER_BYTE, "ANALYZE")
+}
+
+/// Update the statistics of the tables that changed noticeably since the last analysis
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn optimize() -> Result<MaintenanceReport, String> {
+    run_maintenance("optimize", |_, _| OPTIMIZE_INSTRUCTIONS, "PRAGMA optimize")
+}
+
+/// Check the whole database file, `messages` is `["ok"]` or lists up to 100 problems
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn integrity_check() -> Result<MaintenanceReport, String> {
+    run_maintenance(
+        "integrity_check",
+        |size, _| size * INTEGRITY_CHECK_PER_BYTE,
+        "PRAGMA integrity_check",
+    )
+}
+
+mod benches {
+    use super::*;
+    use crate::{admin_execute, execute};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 100000u64;
+
+    fn fill_users() {
+        execute(&format!(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < {COUNT})
+            INSERT INTO users (username, email) SELECT 'user' || id, 'user' || id || '@example.com' FROM n
+            "
+        ));
+        // leaves free pages
+        execute(&format!("DELETE FROM users WHERE user_id > {}", COUNT / 2));
+    }
+
+    // the estimate keeps a margin over the measured cost, but stays within ten times of it
+    fn check_estimate(report: &MaintenanceReport) {
+        assert!(report.instructions <= report.estimated_instructions);
+        assert!(report.instructions * 10 >= report.estimated_instructions);
+    }
+
+    #[bench(raw)]
+    fn bench_maintenance_vacuum() -> BenchResult {
+        fill_users();
+
+        let mut report = None;
+
+        let result = bench_fn(|| {
+            report = Some(vacuum().unwrap());
+        });
+
+        let report = report.unwrap();
+        check_estimate(&report);
+
+        assert!(report.pages_before.freelist_count > 0);
+        assert_eq!(report.pages_after.freelist_count, 0);
+        assert!(report.pages_after.page_count < report.pages_before.page_count);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_maintenance_incremental_vacuum() -> BenchResult {
+        // the default database does not track the free pages
+        assert!(incremental_vacuum(0).is_err());
+
+        admin_execute("PRAGMA auto_vacuum=INCREMENTAL".to_string()).unwrap();
+        vacuum().unwrap();
+
+        fill_users();
+
+        let mut report = None;
+
+        let result = bench_fn(|| {
+            report = Some(incremental_vacuum(0).unwrap());
+        });
+
+        let report = report.unwrap();
+        check_estimate(&report);
+
+        assert!(report.pages_before.freelist_count > 0);
+        assert_eq!(report.pages_after.freelist_count, 0);
+        assert!(report.pages_after.page_count < report.pages_before.page_count);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_maintenance_analyze() -> BenchResult {
+        fill_users();
+
+        let mut report = None;
+
+        let result = bench_fn(|| {
+            report = Some(analyze().unwrap());
+        });
+
+        check_estimate(&report.unwrap());
+
+        let stats: i64 = with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM sqlite_stat1", [], |row| row.get(0))
+        })
+        .unwrap();
+        assert!(stats > 0);
+
+        // nothing changed since the analysis
+        optimize().unwrap();
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_maintenance_integrity_check() -> BenchResult {
+        fill_users();
+
+        let mut report = None;
+
+        let result = bench_fn(|| {
+            report = Some(integrity_check().unwrap());
+        });
+
+        let report = report.unwrap();
+        check_estimate(&report);
+        assert_eq!(report.messages, vec!["ok".to_string()]);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_maintenance_over_budget_is_refused() -> BenchResult {
+        bench_fn(|| {
+            // a 100GB database cannot be vacuumed in one message
+            assert!(check_budget("vacuum", 100_000_000_000 * VACUUM_PER_BYTE).is_err());
+            // the Chinook sample below 1MB is vacuumed by `set_page_size.sh`
+            assert!(check_budget("vacuum", 1_000_000 * VACUUM_PER_BYTE).is_ok());
+            assert!(check_budget("optimize", OPTIMIZE_INSTRUCTIONS).is_ok());
+        })
+    }
+}
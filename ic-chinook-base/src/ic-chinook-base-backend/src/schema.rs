@@ -0,0 +1,295 @@
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+use ic_rusqlite::Connection;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Column {
+    name: String,
+    /// Declared type, empty if the column has none
+    decl_type: String,
+    not_null: bool,
+    /// Default value as an SQL expression
+    default_value: Option<String>,
+    /// Position of the column in the primary key starting at 1, 0 if it is not part of it
+    primary_key: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Index {
+    name: String,
+    unique: bool,
+    /// "c" for `CREATE INDEX`, "u" for a `UNIQUE` and "pk" for a `PRIMARY KEY` constraint
+    origin: String,
+    /// Created with a `WHERE` clause
+    partial: bool,
+    /// Indexed columns, `None` for expressions
+    columns: Vec<Option<String>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ForeignKey {
+    columns: Vec<String>,
+    /// Referenced table
+    table: String,
+    /// Referenced columns, `None` refers to the primary key of `table`
+    to_columns: Vec<Option<String>>,
+    on_update: String,
+    on_delete: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Table {
+    name: String,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct View {
+    name: String,
+    columns: Vec<Column>,
+    sql: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Trigger {
+    name: String,
+    table: String,
+    sql: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Schema {
+    tables: Vec<Table>,
+    views: Vec<View>,
+    triggers: Vec<Trigger>,
+}
+
+/// Names and SQL of the schema objects of the given type, without the internal `sqlite_` objects
+fn objects(conn: &Connection, kind: &str) -> ic_rusqlite::Result<Vec<(String, String, String)>> {
+    let mut stmt = conn.prepare_cached(
+        "
+        SELECT name, tbl_name, IFNULL(sql, '')
+        FROM sqlite_schema
+        WHERE type = ?1 AND name NOT LIKE 'sqlite_%'
+        ORDER BY name
+        ",
+    )?;
+
+    let rows = stmt.query_map([kind], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+    rows.collect()
+}
+
+fn columns(conn: &Connection, table: &str) -> ic_rusqlite::Result<Vec<Column>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?1) ORDER BY cid",
+    )?;
+
+    let rows = stmt.query_map([table], |row| {
+        Ok(Column {
+            name: row.get(0)?,
+            decl_type: row.get(1)?,
+            not_null: row.get(2)?,
+            default_value: row.get(3)?,
+            primary_key: row.get(4)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+fn indexes(conn: &Connection, table: &str) -> ic_rusqlite::Result<Vec<Index>> {
+    let mut list = conn.prepare_cached(
+        "SELECT name, \"unique\", origin, partial FROM pragma_index_list(?1) ORDER BY name",
+    )?;
+    let mut info = conn.prepare_cached("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?;
+
+    let indexes = list
+        .query_map([table], |row| {
+            Ok(Index {
+                name: row.get(0)?,
+                unique: row.get(1)?,
+                origin: row.get(2)?,
+                partial: row.get(3)?,
+                columns: Vec::new(),
+            })
+        })?
+        .collect::<ic_rusqlite::Result<Vec<_>>>()?;
+
+    indexes
+        .into_iter()
+        .map(|mut index| {
+            index.columns = info
+                .query_map([&index.name], |row| row.get(0))?
+                .collect::<ic_rusqlite::Result<_>>()?;
+
+            Ok(index)
+        })
+        .collect()
+}
+
+fn foreign_keys(conn: &Connection, table: &str) -> ic_rusqlite::Result<Vec<ForeignKey>> {
+    let mut stmt = conn.prepare_cached(
+        "
+        SELECT id, \"table\", \"from\", \"to\", on_update, on_delete
+        FROM pragma_foreign_key_list(?1)
+        ORDER BY id, seq
+        ",
+    )?;
+
+    let mut rows = stmt.query([table])?;
+
+    // a key over several columns has one row per column, all with the same id
+    let mut keys: Vec<(i64, ForeignKey)> = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+
+        if keys.last().map(|(last, _)| *last) != Some(id) {
+            keys.push((
+                id,
+                ForeignKey {
+                    columns: Vec::new(),
+                    table: row.get(1)?,
+                    to_columns: Vec::new(),
+                    on_update: row.get(4)?,
+                    on_delete: row.get(5)?,
+                },
+            ));
+        }
+
+        let (_, key) = keys.last_mut().unwrap();
+        key.columns.push(row.get(2)?);
+        key.to_columns.push(row.get(3)?);
+    }
+
+    Ok(keys.into_iter().map(|(_, key)| key).collect())
+}
+
+fn read_schema(conn: &Connection) -> ic_rusqlite::Result<Schema> {
+    let tables = objects(conn, "table")?
+        .into_iter()
+        .map(|(name, _, _)| {
+            Ok(Table {
+                columns: columns(conn, &name)?,
+                indexes: indexes(conn, &name)?,
+                foreign_keys: foreign_keys(conn, &name)?,
+                name,
+            })
+        })
+        .collect::<ic_rusqlite::Result<_>>()?;
+
+    let views = objects(conn, "view")?
+        .into_iter()
+        .map(|(name, _, sql)| {
+            Ok(View {
+                columns: columns(conn, &name)?,
+                name,
+                sql,
+            })
+        })
+        .collect::<ic_rusqlite::Result<_>>()?;
+
+    let triggers = objects(conn, "trigger")?
+        .into_iter()
+        .map(|(name, table, sql)| Trigger { name, table, sql })
+        .collect();
+
+    Ok(Schema {
+        tables,
+        views,
+        triggers,
+    })
+}
+
+/// Describe the tables with their columns, indexes and foreign keys, the views and the triggers
+#[ic_cdk::query]
+fn get_schema() -> Result<Schema, String> {
+    with_connection(|conn| read_schema(&conn)).map_err(|err| format!("{err:?}"))
+}
+
+mod benches {
+    use super::*;
+    use crate::{create_chinook_indices, execute};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    #[bench(raw)]
+    fn bench_get_schema() -> BenchResult {
+        create_chinook_indices();
+
+        execute(
+            "
+            CREATE VIEW IF NOT EXISTS user_totals AS
+            SELECT u.username, SUM(o.amount) AS total
+            FROM users u JOIN orders o ON u.user_id = o.user_id
+            GROUP BY u.user_id
+            ",
+        );
+        execute(
+            "
+            CREATE TRIGGER IF NOT EXISTS users_delete AFTER DELETE ON users BEGIN
+                DELETE FROM orders WHERE user_id = old.user_id;
+            END
+            ",
+        );
+
+        let mut schema = None;
+
+        let result = bench_fn(|| {
+            schema = Some(get_schema().unwrap());
+        });
+
+        let schema = schema.unwrap();
+
+        let table = |name: &str| schema.tables.iter().find(|t| t.name == name).unwrap();
+
+        // the internal tables are not listed
+        assert!(schema.tables.iter().all(|t| !t.name.starts_with("sqlite_")));
+
+        let users = table("users");
+        let columns: Vec<&str> = users.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(columns, ["user_id", "username", "email", "created_at"]);
+        assert_eq!(users.columns[0].primary_key, 1);
+        assert!(users.columns[1].not_null);
+        assert_eq!(
+            users.columns[3].default_value.as_deref(),
+            Some("CURRENT_TIMESTAMP")
+        );
+
+        let orders = table("orders");
+        assert_eq!(orders.foreign_keys.len(), 1);
+        assert_eq!(orders.foreign_keys[0].table, "users");
+        assert_eq!(orders.foreign_keys[0].columns, ["user_id"]);
+        assert_eq!(
+            orders.foreign_keys[0].to_columns,
+            [Some("user_id".to_string())]
+        );
+        assert_eq!(orders.indexes[0].name, "idx_orders_user_id");
+        assert_eq!(orders.indexes[0].columns, [Some("user_id".to_string())]);
+
+        let customers = table("customers");
+        assert_eq!(customers.columns.len(), 13);
+        let indexes: Vec<&str> = customers.indexes.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(
+            indexes,
+            ["idx_customers_first_name", "idx_customers_last_name"]
+        );
+
+        assert_eq!(schema.views.len(), 1);
+        let views_columns: Vec<&str> = schema.views[0]
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(views_columns, ["username", "total"]);
+
+        assert_eq!(schema.triggers.len(), 1);
+        assert_eq!(schema.triggers[0].table, "users");
+
+        result
+    }
+}
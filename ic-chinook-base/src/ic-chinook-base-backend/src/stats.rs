@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::Read;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+
+use crate::common::caller_is_controller;
+use crate::get_db_path;
+
+/// Fields of the 100-byte [database header](https://sqlite.org/fileformat2.html#the_database_header)
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct DbHeader {
+    page_size: u32,
+    page_count: u32,
+    freelist_count: u32,
+    /// Incremented on every schema change
+    schema_version: u32,
+    /// "UTF-8", "UTF-16le" or "UTF-16be"
+    text_encoding: String,
+    user_version: u32,
+    /// Incremented by every transaction changing the file
+    change_counter: u32,
+    /// Version of the SQLite library that last wrote the file, e.g. "3.45.1"
+    sqlite_version: String,
+}
+
+/// Page usage of a table or an index, from the `dbstat` virtual table
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ObjectStats {
+    name: String,
+    /// "table" or "index"
+    kind: String,
+    pages: u64,
+    /// Bytes of all pages
+    size: u64,
+    /// Bytes of the stored records
+    payload: u64,
+    /// Unused bytes of the pages
+    unused: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DbStats {
+    file_size: u64,
+    header: DbHeader,
+    /// Largest objects first, `None` if SQLite was built without `dbstat`
+    objects: Option<Vec<ObjectStats>>,
+}
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+fn parse_header(header: &[u8; 100]) -> Result<DbHeader, String> {
+    if &header[..16] != SQLITE_HEADER {
+        return Err("the database file has no SQLite header".to_string());
+    }
+
+    let u16_at = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+
+    let text_encoding = match u32_at(56) {
+        1 => "UTF-8",
+        2 => "UTF-16le",
+        3 => "UTF-16be",
+        other => return Err(format!("unknown text encoding {other}")),
+    };
+
+    let version = u32_at(96);
+
+    Ok(DbHeader {
+        // the value 1 stands for 65536
+        page_size: match u16_at(16) {
+            1 => 65536,
+            size => size as u32,
+        },
+        page_count: u32_at(28),
+        freelist_count: u32_at(36),
+        schema_version: u32_at(40),
+        text_encoding: text_encoding.to_string(),
+        user_version: u32_at(60),
+        change_counter: u32_at(24),
+        sqlite_version: format!(
+            "{}.{}.{}",
+            version / 1000000,
+            version / 1000 % 1000,
+            version % 1000
+        ),
+    })
+}
+
+fn read_header() -> Result<DbHeader, String> {
+    let mut header = [0u8; 100];
+
+    File::open(get_db_path())
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(|err| format!("{err:?}"))?;
+
+    parse_header(&header)
+}
+
+fn object_stats() -> Result<Option<Vec<ObjectStats>>, String> {
+    with_connection(|conn| {
+        let has_dbstat: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_module_list WHERE name = 'dbstat')",
+            [],
+            |row| row.get(0),
+        )?;
+
+        if !has_dbstat {
+            return Ok(None);
+        }
+
+        // in the aggregate mode `pageno` is the number of pages of the object
+        let mut stmt = conn.prepare(
+            "
+            SELECT d.name, IFNULL(s.type, 'table'), d.pageno, d.pgsize, d.payload, d.unused
+            FROM dbstat AS d
+            LEFT JOIN sqlite_schema AS s ON s.name = d.name
+            WHERE d.aggregate = TRUE
+            ORDER BY d.pgsize DESC
+            ",
+        )?;
+
+        let objects = stmt.query_map([], |row| {
+            Ok(ObjectStats {
+                name: row.get(0)?,
+                kind: row.get(1)?,
+                pages: row.get(2)?,
+                size: row.get(3)?,
+                payload: row.get(4)?,
+                unused: row.get(5)?,
+            })
+        })?;
+
+        objects.collect::<ic_rusqlite::Result<Vec<_>>>().map(Some)
+    })
+    .map_err(|err| format!("{err:?}"))
+}
+
+/// Describe the database file: the header fields and the pages used by each table and index
+///
+/// The per-object statistics read every page of the database, so only the controllers can call it.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn db_stats() -> Result<DbStats, String> {
+    let objects = object_stats()?;
+
+    Ok(DbStats {
+        file_size: std::fs::metadata(get_db_path())
+            .map_err(|err| format!("{err:?}"))?
+            .len(),
+        header: read_header()?,
+        objects,
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::execute;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 100000u64;
+
+    fn pragma(name: &str) -> u32 {
+        with_connection(|conn| conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0)))
+            .unwrap()
+    }
+
+    #[bench(raw)]
+    fn bench_db_stats() -> BenchResult {
+        execute(&format!(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < {COUNT})
+            INSERT INTO users (username, email) SELECT 'user' || id, 'user' || id || '@example.com' FROM n
+            "
+        ));
+        execute(&format!("DELETE FROM users WHERE user_id > {}", COUNT / 2));
+
+        let mut stats = None;
+
+        let result = bench_fn(|| {
+            stats = Some(db_stats().unwrap());
+        });
+
+        let stats = stats.unwrap();
+
+        // the header agrees with the connection
+        let header = &stats.header;
+        assert_eq!(header.page_size, pragma("page_size"));
+        assert_eq!(header.page_count, pragma("page_count"));
+        assert_eq!(header.freelist_count, pragma("freelist_count"));
+        assert!(header.freelist_count > 0);
+        assert_eq!(header.schema_version, pragma("schema_version"));
+        assert_eq!(header.user_version, pragma("user_version"));
+        assert_eq!(header.text_encoding, "UTF-8");
+
+        if let Some(objects) = stats.objects {
+            let object = |name: &str| objects.iter().find(|o| o.name == name).unwrap();
+
+            assert_eq!(object("users").kind, "table");
+            assert_eq!(object("idx_users_email").kind, "index");
+            assert!(object("idx_users_email").pages > 0);
+
+            // every page except the free ones belongs to an object
+            let used: u64 = objects.iter().map(|o| o.pages).sum();
+            assert!(used + header.freelist_count as u64 <= header.page_count as u64);
+        }
+
+        // the text encoding is validated
+        let mut header = [0u8; 100];
+        header[..16].copy_from_slice(SQLITE_HEADER);
+        assert!(parse_header(&header).is_err());
+
+        result
+    }
+}
@@ -0,0 +1,350 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use crate::common::caller_is_controller;
+use crate::common::database_is_writable;
+use crate::common::sha256_file;
+use crate::get_db_path;
+use crate::maintenance::check_budget;
+use crate::maintenance::QUICK_CHECK_PER_BYTE;
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+struct Upload {
+    size: u64,
+    sha256: String,
+    /// Received byte ranges, start -> end (exclusive), never overlapping or adjacent
+    received: BTreeMap<u64, u64>,
+}
+
+impl Upload {
+    fn add_range(&mut self, mut start: u64, mut end: u64) {
+        // merge with the ranges overlapping or touching [start, end)
+        let touching: Vec<(u64, u64)> = self
+            .received
+            .range(..=end)
+            .filter(|(_, &e)| e >= start)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+
+        for (s, e) in touching {
+            self.received.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+
+        self.received.insert(start, end);
+    }
+
+    fn missing(&self) -> Vec<(u64, u64)> {
+        let mut missing = Vec::new();
+        let mut pos = 0;
+
+        for (&s, &e) in &self.received {
+            if s > pos {
+                missing.push((pos, s));
+            }
+            pos = e;
+        }
+
+        if pos < self.size {
+            missing.push((pos, self.size));
+        }
+
+        missing
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UploadStatus {
+    size: u64,
+    received: u64,
+    /// Byte ranges [start, end) that were not uploaded yet
+    missing: Vec<(u64, u64)>,
+}
+
+thread_local! {
+    static UPLOAD: RefCell<Option<Upload>> = const { RefCell::new(None) };
+}
+
+/// Staging file receiving the uploaded chunks, next to the database file
+fn upload_path() -> String {
+    format!("{}.upload", get_db_path())
+}
+
+fn io_error(err: std::io::Error) -> String {
+    format!("{err:?}")
+}
+
+fn no_upload() -> String {
+    "no upload in progress, call begin_upload first".to_string()
+}
+
+/// Start a new upload of a database file with the given size and hex-encoded SHA-256, any previous upload is discarded
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn begin_upload(size: u64, sha256: String) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(upload_path())
+        .map_err(io_error)?;
+
+    file.set_len(size).map_err(io_error)?;
+
+    UPLOAD.with_borrow_mut(|upload| {
+        *upload = Some(Upload {
+            size,
+            sha256: sha256.to_lowercase(),
+            received: BTreeMap::new(),
+        })
+    });
+
+    Ok(())
+}
+
+/// Write a chunk at the given offset, chunks may arrive in any order and may be sent again
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn upload_chunk(offset: u64, content: Vec<u8>) -> Result<(), String> {
+    UPLOAD.with_borrow_mut(|upload| {
+        let upload = upload.as_mut().ok_or_else(no_upload)?;
+
+        let end = offset
+            .checked_add(content.len() as u64)
+            .filter(|&end| end <= upload.size)
+            .ok_or_else(|| {
+                format!(
+                    "chunk of {} bytes at offset {offset} exceeds the file size {}",
+                    content.len(),
+                    upload.size
+                )
+            })?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(upload_path())
+            .map_err(io_error)?;
+
+        file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        file.write_all(&content).map_err(io_error)?;
+
+        upload.add_range(offset, end);
+
+        Ok(())
+    })
+}
+
+#[ic_cdk::query(guard = "caller_is_controller")]
+fn upload_status() -> Result<UploadStatus, String> {
+    UPLOAD.with_borrow(|upload| {
+        let upload = upload.as_ref().ok_or_else(no_upload)?;
+
+        let missing = upload.missing();
+
+        Ok(UploadStatus {
+            size: upload.size,
+            received: upload.size - missing.iter().map(|(s, e)| e - s).sum::<u64>(),
+            missing,
+        })
+    })
+}
+
+fn verify(upload: &Upload) -> Result<(), String> {
+    let missing = upload.missing();
+    if !missing.is_empty() {
+        return Err(format!("upload is incomplete, missing ranges: {missing:?}"));
+    }
+
+    let path = upload_path();
+
+    let (_, sha256) = sha256_file(&path).map_err(io_error)?;
+    if sha256 != upload.sha256 {
+        return Err(format!(
+            "checksum mismatch: expected {}, received {sha256}",
+            upload.sha256
+        ));
+    }
+
+    let mut header = [0u8; 16];
+    File::open(&path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(io_error)?;
+
+    if &header != SQLITE_HEADER {
+        return Err("the uploaded file is not an SQLite database".to_string());
+    }
+
+    // the full integrity check costs twice as much, quick_check still reads and checks every page
+    check_budget("quick_check", upload.size * QUICK_CHECK_PER_BYTE)?;
+
+    let conn = ic_rusqlite::Connection::open(&path).map_err(|err| format!("{err:?}"))?;
+    let check = conn
+        .prepare("PRAGMA quick_check")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<ic_rusqlite::Result<Vec<_>>>()
+        })
+        .map_err(|err| format!("{err:?}"))?;
+    conn.close().map_err(|(_, err)| format!("{err:?}"))?;
+
+    if check != ["ok"] {
+        return Err(format!("quick check failed: {}", check.join("; ")));
+    }
+
+    Ok(())
+}
+
+/// Verify the uploaded file with `PRAGMA quick_check` and copy it over the memory-mounted database file
+///
+/// The replaced database is opened once before the call returns, if that fails the call traps
+/// and the previous database is kept.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn finish_upload() -> Result<(), String> {
+    UPLOAD.with_borrow_mut(|upload| {
+        verify(upload.as_ref().ok_or_else(no_upload)?)?;
+
+        ic_rusqlite::close_connection();
+
+        let mut source = File::open(upload_path()).map_err(io_error)?;
+        let mut target = OpenOptions::new()
+            .write(true)
+            .create(false)
+            .truncate(true)
+            .open(get_db_path())
+            .map_err(io_error)?;
+
+        std::io::copy(&mut source, &mut target).map_err(io_error)?;
+        drop(target);
+
+        // a trap rolls back the copy together with the rest of the message
+        let tables = ic_rusqlite::with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM sqlite_schema", [], |row| {
+                row.get::<_, i64>(0)
+            })
+        });
+        if let Err(err) = tables {
+            ic_cdk::trap(format!("the replaced database cannot be opened: {err:?}"));
+        }
+
+        std::fs::remove_file(upload_path()).map_err(io_error)?;
+        *upload = None;
+
+        Ok(())
+    })
+}
+
+/// Discard the current upload
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn abort_upload() {
+    UPLOAD.with_borrow_mut(|upload| {
+        if upload.take().is_some() {
+            let _ = std::fs::remove_file(upload_path());
+        }
+    });
+}
+
+mod benches {
+    use super::*;
+    use crate::{execute, query};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    use sha2::Digest;
+    use sha2::Sha256;
+
+    // small chunks, so the database is uploaded in many pieces
+    const TEST_CHUNK_SIZE: usize = 64 * 1024;
+
+    const CUSTOMERS: u64 = 50000;
+
+    fn count_customers() -> String {
+        query("SELECT COUNT(*) FROM customers".to_string())[0][0]
+            .clone()
+            .unwrap()
+    }
+
+    fn upload(db: &[u8]) -> Result<(), String> {
+        begin_upload(db.len() as u64, hex::encode(Sha256::digest(db)))?;
+        for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate() {
+            upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec())?;
+        }
+        finish_upload()
+    }
+
+    #[bench(raw)]
+    fn bench_upload_chinook_customers_chunked() -> BenchResult {
+        // customers spread over the countries of the Chinook sample, with a support representative each
+        execute(&format!(
+            "
+            WITH RECURSIVE n(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM n WHERE id < {CUSTOMERS})
+            INSERT INTO customers (FirstName, LastName, City, Country, Email, SupportRepId)
+            SELECT 'First' || id, 'Last' || id, 'City' || (id % 50),
+                   CASE id % 4 WHEN 0 THEN 'USA' WHEN 1 THEN 'Canada' WHEN 2 THEN 'Brazil' ELSE 'France' END,
+                   'customer' || id || '@chinookcorp.com', 3 + id % 3
+            FROM n
+            "
+        ));
+        execute("CREATE INDEX IF NOT EXISTS idx_customers_last_name ON customers(lastname)");
+
+        ic_rusqlite::close_connection();
+        let db = std::fs::read(get_db_path()).unwrap();
+        let sha256 = hex::encode(Sha256::digest(&db));
+        let customers = count_customers();
+
+        execute("DELETE FROM customers WHERE CustomerId > 1000");
+        assert_eq!(count_customers(), "1000");
+        assert_ne!(count_customers(), customers);
+
+        // a wrong checksum is rejected
+        begin_upload(db.len() as u64, "00".repeat(32)).unwrap();
+        // chunks past the end are rejected, also when the end overflows
+        assert!(upload_chunk(db.len() as u64, vec![0]).is_err());
+        assert!(upload_chunk(u64::MAX, vec![0]).is_err());
+        for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate() {
+            upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec()).unwrap();
+        }
+        assert!(finish_upload().is_err());
+
+        // a file with a valid header and checksum but a broken page is rejected by quick_check
+        let page_size = u16::from_be_bytes([db[16], db[17]]) as usize;
+        let mut corrupted = db.clone();
+        corrupted[page_size] = 0xff;
+        assert!(upload(&corrupted).is_err());
+        assert_ne!(count_customers(), customers);
+
+        let result = bench_fn(|| {
+            begin_upload(db.len() as u64, sha256.clone()).unwrap();
+
+            // send the chunks in reverse order, the first one twice
+            upload_chunk(0, db[..TEST_CHUNK_SIZE].to_vec()).unwrap();
+            for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate().rev() {
+                upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec()).unwrap();
+            }
+
+            assert!(upload_status().unwrap().missing.is_empty());
+
+            finish_upload().unwrap();
+        });
+
+        // the database was replaced with the uploaded one, including its index
+        assert_eq!(count_customers(), customers);
+        assert_eq!(
+            query(
+                "SELECT COUNT(*) FROM sqlite_schema WHERE name = 'idx_customers_last_name'"
+                    .to_string()
+            )[0][0]
+                .as_deref(),
+            Some("1")
+        );
+
+        result
+    }
+}
@@ -0,0 +1,38 @@
+#!/bin/bash
+
+# run after bench1.sh, the full-text index is created on the filled person table only,
+# so the triggers of the index do not change the insert, update and delete results of bench1.sh
+
+backend=ic-sqlite-bench-backend
+
+# create the full-text index of person names, the triggers keep it in sync with the table
+dfx canister call $backend execute "create virtual table person_fts using fts5(name, content='person', content_rowid='id')"
+dfx canister call $backend execute "create trigger person_fts_insert after insert on person begin insert into person_fts(rowid, name) values (new.id, new.name); end"
+dfx canister call $backend execute "create trigger person_fts_delete after delete on person begin insert into person_fts(person_fts, rowid, name) values ('delete', old.id, old.name); end"
+dfx canister call $backend execute "create trigger person_fts_update after update of name on person begin insert into person_fts(person_fts, rowid, name) values ('delete', old.id, old.name); insert into person_fts(rowid, name) values (new.id, new.name); end"
+
+# index the existing persons
+dfx canister call $backend execute "insert into person_fts(person_fts) values ('rebuild')"
+
+for COUNTER in 10000 100000 500000 990000;
+do
+
+  echo "--- ${COUNTER} ---"
+
+  # query_by_like_prefix performance counter
+  like=$(dfx canister call $backend bench1_query_person_by_like_prefix "(${COUNTER})")
+  echo "$like"
+
+  # query_by_fts_name performance counter
+  fts=$(dfx canister call $backend bench1_query_person_by_fts_name "(${COUNTER})")
+  echo "$fts"
+
+  # both searches must find the same persons
+  like_rows=$(echo "$like" | grep -o 'rows: [0-9]*')
+  fts_rows=$(echo "$fts" | grep -o 'rows: [0-9]*')
+  if [ -z "$like_rows" ] || [ "$like_rows" != "$fts_rows" ]; then
+    echo "the LIKE and FTS searches found different rows"
+    exit 1
+  fi
+
+done
@@ -210,6 +210,94 @@ fn bench1_query_person_by_like_name(offset: usize) -> Result {
     })
 }
 
+/// The prefix search `like 'name%'`, unlike `bench1_query_person_by_like_name` the pattern is bound without quotes,
+/// so it finds the same rows as `bench1_query_person_by_fts_name`.
+#[ic_cdk::query]
+fn bench1_query_person_by_like_prefix(offset: usize) -> Result {
+    with_connection(|conn| {
+        let name = format!("person{:?}", offset + 1);
+        let mut stmt = match conn.prepare("select * from person where name like ?1") {
+            Ok(e) => e,
+            Err(err) => {
+                return Err(Error::CanisterError {
+                    message: format!("{err:?}"),
+                })
+            }
+        };
+        let iter = match stmt.query_map((format!("{name}%"),), |row| {
+            Ok(Person {
+                id: row.get(0).unwrap(),
+                name: row.get(1).unwrap(),
+                age: row.get(2).unwrap(),
+                gender: row.get(3).unwrap(),
+            })
+        }) {
+            Ok(e) => e,
+            Err(err) => {
+                return Err(Error::CanisterError {
+                    message: format!("{err:?}"),
+                })
+            }
+        };
+        let mut arr = Vec::new();
+        for ite in iter {
+            arr.push(ite.unwrap());
+        }
+        let res = serde_json::to_string(&arr).unwrap();
+        ic_cdk::eprintln!("query_by_like_prefix: {:?}", res);
+        Ok(format!(
+            "query_by_like_prefix rows: {:?} performance_counter: {:?}",
+            arr.len(),
+            ic_cdk::api::performance_counter(0)
+        ))
+    })
+}
+
+/// The same search as `bench1_query_person_by_like_prefix` through the FTS5 index `person_fts` (see `bench1_fts.sh`).
+#[ic_cdk::query]
+fn bench1_query_person_by_fts_name(offset: usize) -> Result {
+    with_connection(|conn| {
+        let name = format!("person{:?}", offset + 1);
+        let mut stmt = match conn.prepare(
+            "select person.* from person_fts join person on person.id = person_fts.rowid where person_fts match ?1",
+        ) {
+            Ok(e) => e,
+            Err(err) => {
+                return Err(Error::CanisterError {
+                    message: format!("{err:?}"),
+                })
+            }
+        };
+        // prefix query, the FTS5 counterpart of `like 'name%'`
+        let iter = match stmt.query_map((format!("{name}*"),), |row| {
+            Ok(Person {
+                id: row.get(0).unwrap(),
+                name: row.get(1).unwrap(),
+                age: row.get(2).unwrap(),
+                gender: row.get(3).unwrap(),
+            })
+        }) {
+            Ok(e) => e,
+            Err(err) => {
+                return Err(Error::CanisterError {
+                    message: format!("{err:?}"),
+                })
+            }
+        };
+        let mut arr = Vec::new();
+        for ite in iter {
+            arr.push(ite.unwrap());
+        }
+        let res = serde_json::to_string(&arr).unwrap();
+        ic_cdk::eprintln!("query_by_fts_name: {:?}", res);
+        Ok(format!(
+            "query_by_fts_name rows: {:?} performance_counter: {:?}",
+            arr.len(),
+            ic_cdk::api::performance_counter(0)
+        ))
+    })
+}
+
 #[ic_cdk::query]
 fn bench1_query_person_by_limit_offset(limit: usize, offset: usize) -> Result {
     with_connection(|conn| {
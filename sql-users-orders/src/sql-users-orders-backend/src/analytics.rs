@@ -0,0 +1,191 @@
+//! Analytical queries of the dashboards, benchmarked with and without `create_indices()` where the indices apply.
+
+/// Total spend per user.
+pub const SPEND_PER_USER: &str = "
+    SELECT user_id, COUNT(*) AS orders, SUM(amount) AS total
+    FROM orders
+    GROUP BY user_id
+";
+
+/// Top 10 users by spend.
+pub const TOP_USERS_BY_SPEND: &str = "
+    SELECT u.user_id, u.username, SUM(o.amount) AS total
+    FROM users u
+    JOIN orders o ON o.user_id = u.user_id
+    GROUP BY u.user_id
+    ORDER BY total DESC
+    LIMIT 10
+";
+
+/// Running total and amount rank of each order within the orders of its user.
+pub const RUNNING_TOTALS: &str = "
+    SELECT order_id, user_id, amount,
+        SUM(amount) OVER (PARTITION BY user_id ORDER BY created_at, order_id) AS running_total,
+        RANK() OVER (PARTITION BY user_id ORDER BY amount DESC) AS amount_rank
+    FROM orders
+    WHERE user_id < 1000
+";
+
+/// Monthly order count, revenue and average order value.
+pub const MONTHLY_REVENUE: &str = "
+    SELECT strftime('%Y-%m', created_at) AS month, COUNT(*) AS orders, SUM(amount) AS revenue, AVG(amount) AS average
+    FROM orders
+    GROUP BY month
+    ORDER BY month
+";
+
+/// Users whose largest order exceeds five times their average order.
+pub const OUTLIER_USERS: &str = "
+    SELECT u.user_id, u.username
+    FROM users u
+    WHERE u.user_id < 100
+      AND (SELECT MAX(o.amount) FROM orders o WHERE o.user_id = u.user_id)
+        > 5 * (SELECT AVG(o.amount) FROM orders o WHERE o.user_id = u.user_id)
+";
+
+mod benches {
+    use super::*;
+    use crate::{add_orders, add_users, create_indices, query, QueryOutput, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    fn integer(value: &SqlValue) -> i64 {
+        match value {
+            SqlValue::Integer(v) => *v,
+            v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    fn real(value: &SqlValue) -> f64 {
+        match value {
+            SqlValue::Real(v) => *v,
+            SqlValue::Integer(v) => *v as f64,
+            v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    fn bench_analytics(sql: &str, indexed: bool, check: impl FnOnce(&QueryOutput)) -> BenchResult {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+
+        if indexed {
+            create_indices();
+        }
+
+        let mut output = None;
+
+        let result = bench_fn(|| {
+            output = Some(query(sql.to_string()).unwrap());
+        });
+
+        check(&output.unwrap());
+
+        result
+    }
+
+    fn check_spend_per_user(output: &QueryOutput) {
+        let orders: i64 = output.rows.iter().map(|row| integer(&row[1])).sum();
+        assert_eq!(orders, COUNT as i64);
+    }
+
+    fn check_top_users(output: &QueryOutput) {
+        assert_eq!(output.rows.len(), 10);
+
+        let totals: Vec<f64> = output.rows.iter().map(|row| real(&row[2])).collect();
+        assert!(totals.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    fn check_running_totals(output: &QueryOutput) {
+        assert!(!output.rows.is_empty());
+
+        for row in &output.rows {
+            assert!(real(&row[3]) >= real(&row[2]) - 1e-6);
+            assert!(integer(&row[4]) >= 1);
+        }
+    }
+
+    fn check_monthly_revenue(output: &QueryOutput) {
+        // the orders are spread over the whole year
+        assert_eq!(output.rows.len(), 12);
+
+        let orders: i64 = output.rows.iter().map(|row| integer(&row[1])).sum();
+        assert_eq!(orders, COUNT as i64);
+    }
+
+    fn check_outlier_users(output: &QueryOutput) {
+        // with the Zipf skew some of the first users have enough orders for an outlier
+        assert!(!output.rows.is_empty());
+
+        for row in &output.rows {
+            let stats = query(format!(
+                "SELECT MAX(amount), AVG(amount) FROM orders WHERE user_id = {}",
+                integer(&row[0])
+            ))
+            .unwrap();
+
+            assert!(real(&stats.rows[0][0]) > 5.0 * real(&stats.rows[0][1]));
+        }
+
+        // no outlier is missing
+        let expected = query(
+            "
+            SELECT COUNT(*) FROM (
+                SELECT user_id FROM orders WHERE user_id < 100
+                GROUP BY user_id HAVING MAX(amount) > 5 * AVG(amount)
+            )
+            "
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(integer(&expected.rows[0][0]), output.rows.len() as i64);
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_spend_per_user() -> BenchResult {
+        bench_analytics(SPEND_PER_USER, false, check_spend_per_user)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_spend_per_user_indexed() -> BenchResult {
+        bench_analytics(SPEND_PER_USER, true, check_spend_per_user)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_top_users_by_spend() -> BenchResult {
+        bench_analytics(TOP_USERS_BY_SPEND, false, check_top_users)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_top_users_by_spend_indexed() -> BenchResult {
+        bench_analytics(TOP_USERS_BY_SPEND, true, check_top_users)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_running_totals() -> BenchResult {
+        bench_analytics(RUNNING_TOTALS, false, check_running_totals)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_running_totals_indexed() -> BenchResult {
+        bench_analytics(RUNNING_TOTALS, true, check_running_totals)
+    }
+
+    // no index covers `created_at`, so there is no `_indexed` variant
+    #[bench(raw)]
+    fn bench_analytics_monthly_revenue() -> BenchResult {
+        bench_analytics(MONTHLY_REVENUE, false, check_monthly_revenue)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_outlier_users() -> BenchResult {
+        bench_analytics(OUTLIER_USERS, false, check_outlier_users)
+    }
+
+    #[bench(raw)]
+    fn bench_analytics_outlier_users_indexed() -> BenchResult {
+        bench_analytics(OUTLIER_USERS, true, check_outlier_users)
+    }
+}
@@ -0,0 +1,83 @@
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+
+use crate::Result;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct DatabaseCheck {
+    /// Result of `PRAGMA integrity_check`, "ok" for a healthy database
+    integrity_check: String,
+    users: u64,
+    orders: u64,
+    /// Largest order id, shows whether the `AUTOINCREMENT` counter moved
+    max_order_id: u64,
+}
+
+/// Check the database file and count the rows, used to compare the state before and after a trap.
+#[ic_cdk::query]
+fn check_database() -> Result<DatabaseCheck> {
+    Ok(with_connection(|conn| {
+        let integrity_check = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+
+        conn.query_row(
+            "SELECT
+                (SELECT COUNT(*) FROM users),
+                (SELECT COUNT(*) FROM orders),
+                (SELECT IFNULL(MAX(order_id), 0) FROM orders)",
+            [],
+            |row| {
+                Ok(DatabaseCheck {
+                    integrity_check,
+                    users: row.get(0)?,
+                    orders: row.get(1)?,
+                    max_order_id: row.get(2)?,
+                })
+            },
+        )
+    })?)
+}
+
+// Only built with the `trap-testing` feature, so the trapping endpoint is never deployed by accident.
+#[cfg(feature = "trap-testing")]
+mod trap_testing {
+    use ic_rusqlite::with_connection;
+
+    use crate::export::caller_is_controller;
+    use crate::export::database_is_writable;
+    use crate::insert_order;
+    use crate::order_generator;
+    use crate::Result;
+    use crate::INSERT_ORDER;
+
+    /// Same as `add_orders`, but traps after inserting `trap_at` rows inside the open transaction.
+    ///
+    /// For testing only: the IC discards all changes of the trapping message, including the
+    /// written database pages and the state of the cached connection (see `test_trap_safety.sh`).
+    #[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+    fn add_orders_trap_at(offset: u64, count: u64, user_count: u64, trap_at: u64) -> Result<()> {
+        let orders = order_generator(user_count)?;
+
+        with_connection(|mut conn| {
+            let tx = conn.transaction().unwrap();
+
+            {
+                let mut stmt = tx.prepare_cached(INSERT_ORDER).unwrap();
+
+                for i in 0..count {
+                    if i == trap_at {
+                        ic_cdk::trap(format!("trap after inserting {i} orders"));
+                    }
+
+                    insert_order(&mut stmt, &orders, offset + i)
+                        .expect("insert of an order failed!");
+                }
+            }
+
+            tx.commit().expect("COMMIT ORDER INSERTION FAILED!");
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::params_from_iter;
+use ic_rusqlite::with_connection;
+
+use crate::collect_rows;
+use crate::ColumnInfo;
+use crate::Error;
+use crate::QueryResult;
+use crate::Result;
+use crate::SqlValue;
+
+/// An open query, iterated page by page using the last seen value of a unique key column.
+struct Cursor {
+    sql: String,
+    params: Vec<SqlValue>,
+    key_column: String,
+    descending: bool,
+    last_key: Option<SqlValue>,
+    done: bool,
+    /// Time of the last `query_open` or `query_next` call, in nanoseconds
+    last_used: u64,
+}
+
+impl Cursor {
+    fn page_sql(&self) -> String {
+        let key = format!("\"{}\"", self.key_column.replace('"', "\"\""));
+        let (order, cmp) = if self.descending {
+            ("DESC", "<")
+        } else {
+            ("ASC", ">")
+        };
+
+        // the paging parameters follow the positional parameters of the query
+        let n = self.params.len();
+
+        match self.last_key {
+            None => format!(
+                "SELECT * FROM ({}) ORDER BY {key} {order} LIMIT ?{}",
+                self.sql,
+                n + 1
+            ),
+            Some(_) => format!(
+                "SELECT * FROM ({}) WHERE {key} {cmp} ?{} ORDER BY {key} {order} LIMIT ?{}",
+                self.sql,
+                n + 1,
+                n + 2
+            ),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct QueryPage {
+    columns: Vec<ColumnInfo>,
+    rows: Vec<Vec<SqlValue>>,
+    /// No more rows left, the cursor can be closed
+    done: bool,
+}
+
+/// Open cursors, opening one more closes the least recently used
+const MAX_CURSORS: usize = 100;
+
+/// Rows of one page at most, larger `max_rows` are clamped to it
+const MAX_PAGE_ROWS: u64 = 10_000;
+
+/// Cursors not used for 10 minutes are closed
+const CURSOR_TTL: u64 = 10 * 60 * 1_000_000_000;
+
+thread_local! {
+    static CURSORS: RefCell<BTreeMap<u64, Cursor>> = const { RefCell::new(BTreeMap::new()) };
+
+    static NEXT_CURSOR_ID: Cell<u64> = const { Cell::new(1) };
+}
+
+/// Close the expired cursors and make room for a new one
+fn expire_cursors(cursors: &mut BTreeMap<u64, Cursor>, now: u64) {
+    cursors.retain(|_, cursor| now.saturating_sub(cursor.last_used) < CURSOR_TTL);
+
+    while cursors.len() >= MAX_CURSORS {
+        let oldest = cursors
+            .iter()
+            .min_by_key(|(_, cursor)| cursor.last_used)
+            .map(|(&id, _)| id)
+            .unwrap();
+
+        cursors.remove(&oldest);
+    }
+}
+
+// approximate size of a value in the reply
+fn value_size(value: &SqlValue) -> u64 {
+    match value {
+        SqlValue::Null => 1,
+        SqlValue::Integer(_) | SqlValue::Real(_) => 9,
+        SqlValue::Text(v) => v.len() as u64 + 5,
+        SqlValue::Blob(v) => v.len() as u64 + 5,
+    }
+}
+
+/// Open a cursor over the query results ordered by `key_column`, returns the cursor id.
+///
+/// The key column must be unique within the results, the query may use positional parameters `?1`, `?2`, ...
+/// Close the cursor with `query_close` when done, cursors left open expire after 10 minutes without use
+/// and at most 100 are kept, opening more closes the least recently used ones.
+#[ic_cdk::update]
+fn query_open(
+    sql: String,
+    params: Vec<SqlValue>,
+    key_column: String,
+    descending: bool,
+) -> Result<u64> {
+    let cursor = Cursor {
+        sql: sql.trim().trim_end_matches(';').to_string(),
+        params,
+        key_column,
+        descending,
+        last_key: None,
+        done: false,
+        last_used: ic_cdk::api::time(),
+    };
+
+    // validate the query and the key column before handing out the cursor
+    with_connection(|conn| {
+        let stmt = conn.prepare_cached(&cursor.page_sql())?;
+        stmt.column_index(&cursor.key_column)?;
+
+        Ok::<(), Error>(())
+    })?;
+
+    let id = NEXT_CURSOR_ID.with(|next| {
+        let id = next.get();
+        next.set(id + 1);
+        id
+    });
+
+    CURSORS.with_borrow_mut(|cursors| {
+        expire_cursors(cursors, cursor.last_used);
+        cursors.insert(id, cursor)
+    });
+
+    Ok(id)
+}
+
+/// Return the next page of at most `max_rows` rows (up to `MAX_PAGE_ROWS`), the page is cut once it exceeds approximately `max_bytes`.
+#[ic_cdk::update]
+fn query_next(cursor: u64, max_rows: u64, max_bytes: u64) -> QueryResult<QueryPage> {
+    CURSORS.with_borrow_mut(|cursors| {
+        let now = ic_cdk::api::time();
+
+        let cursor = cursors
+            .get_mut(&cursor)
+            .filter(|c| now.saturating_sub(c.last_used) < CURSOR_TTL)
+            .ok_or(Error::CanisterError {
+                message: format!("cursor {cursor} not found or expired"),
+            })?;
+
+        cursor.last_used = now;
+
+        if cursor.done {
+            return Ok(QueryPage {
+                columns: Vec::new(),
+                rows: Vec::new(),
+                done: true,
+            });
+        }
+
+        let max_rows = max_rows.clamp(1, MAX_PAGE_ROWS);
+
+        let mut output = with_connection(|conn| {
+            let mut stmt = conn.prepare_cached(&cursor.page_sql())?;
+
+            let limit = SqlValue::Integer(max_rows as i64);
+            let params = cursor
+                .params
+                .iter()
+                .chain(cursor.last_key.iter())
+                .chain(std::iter::once(&limit));
+
+            collect_rows(&mut stmt, params_from_iter(params))
+        })?;
+
+        let fetched = output.rows.len() as u64;
+
+        // always return at least one row, so the cursor advances
+        let mut size = 0;
+        let mut keep = 0;
+        for row in &output.rows {
+            size += row.iter().map(value_size).sum::<u64>();
+            if keep > 0 && size > max_bytes {
+                break;
+            }
+            keep += 1;
+        }
+        output.rows.truncate(keep);
+
+        let key_idx = output
+            .columns
+            .iter()
+            .position(|c| c.name == cursor.key_column)
+            .ok_or(Error::CanisterError {
+                message: format!("key column {} not found", cursor.key_column),
+            })?;
+
+        if let Some(row) = output.rows.last() {
+            cursor.last_key = Some(row[key_idx].clone());
+        }
+
+        cursor.done = fetched < max_rows && keep as u64 == fetched;
+
+        Ok(QueryPage {
+            columns: output.columns,
+            rows: output.rows,
+            done: cursor.done,
+        })
+    })
+}
+
+/// Close the cursor and release its state
+#[ic_cdk::update]
+fn query_close(cursor: u64) {
+    CURSORS.with_borrow_mut(|cursors| cursors.remove(&cursor));
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_orders, add_users, create_indices, query_with_params};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    // rows per page
+    const PAGE_SIZE: u64 = 1000;
+
+    // pages to scan
+    const PAGES: u64 = 100;
+
+    fn fill_orders() {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+    }
+
+    #[bench(raw)]
+    fn bench_scan_orders_limit_offset() -> BenchResult {
+        fill_orders();
+
+        bench_fn(|| {
+            for page in 0..PAGES {
+                let res = query_with_params(
+                    "SELECT order_id, user_id, amount FROM orders ORDER BY order_id LIMIT ?1 OFFSET ?2"
+                        .to_string(),
+                    vec![
+                        SqlValue::Integer(PAGE_SIZE as i64),
+                        SqlValue::Integer((page * PAGE_SIZE) as i64),
+                    ],
+                )
+                .unwrap();
+
+                assert_eq!(res.rows.len() as u64, PAGE_SIZE);
+            }
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_scan_orders_cursor() -> BenchResult {
+        fill_orders();
+
+        let result = bench_fn(|| {
+            let cursor = query_open(
+                "SELECT order_id, user_id, amount FROM orders".to_string(),
+                vec![],
+                "order_id".to_string(),
+                false,
+            )
+            .unwrap();
+
+            for _ in 0..PAGES {
+                let page = query_next(cursor, PAGE_SIZE, u64::MAX).unwrap();
+
+                assert_eq!(page.rows.len() as u64, PAGE_SIZE);
+            }
+
+            query_close(cursor);
+        });
+
+        // an unlimited page is clamped, it does not return all orders at once
+        let cursor = query_open(
+            "SELECT order_id, user_id, amount FROM orders".to_string(),
+            vec![],
+            "order_id".to_string(),
+            false,
+        )
+        .unwrap();
+
+        let page = query_next(cursor, u64::MAX, u64::MAX).unwrap();
+        assert_eq!(page.rows.len() as u64, MAX_PAGE_ROWS);
+        assert!(!page.done);
+
+        query_close(cursor);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_select_with_join_cursor() -> BenchResult {
+        fill_orders();
+
+        bench_fn(|| {
+            let cursor = query_open(
+                r#"
+                SELECT u.user_id, u.username, o.order_id, o.amount
+                FROM users u
+                JOIN orders o ON u.user_id = o.user_id
+                WHERE u.user_id < ?1
+                "#
+                .to_string(),
+                vec![SqlValue::Integer(1000)],
+                "order_id".to_string(),
+                true,
+            )
+            .unwrap();
+
+            let mut rows = 0;
+            loop {
+                let page = query_next(cursor, PAGE_SIZE, 1_000_000).unwrap();
+                rows += page.rows.len();
+
+                if page.done {
+                    break;
+                }
+            }
+
+            query_close(cursor);
+
+            assert!(rows > 0);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_open_cursors_are_capped() -> BenchResult {
+        add_users(0, 10).unwrap();
+
+        let open = || {
+            query_open(
+                "SELECT user_id, username FROM users".to_string(),
+                vec![],
+                "user_id".to_string(),
+                false,
+            )
+            .unwrap()
+        };
+
+        let first = open();
+
+        let result = bench_fn(|| {
+            for _ in 0..MAX_CURSORS {
+                open();
+            }
+        });
+
+        // abandoned cursors do not pile up, the least recently used one was closed
+        assert_eq!(CURSORS.with_borrow(|cursors| cursors.len()), MAX_CURSORS);
+        assert!(query_next(first, 10, u64::MAX).is_err());
+
+        // expired cursors are closed
+        let now = ic_cdk::api::time();
+        CURSORS.with_borrow_mut(|cursors| expire_cursors(cursors, now + CURSOR_TTL));
+        assert!(CURSORS.with_borrow(|cursors| cursors.is_empty()));
+
+        result
+    }
+}
@@ -0,0 +1,84 @@
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+
+use crate::Result;
+
+/// One row of `EXPLAIN QUERY PLAN`, the steps form a tree through `parent`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct PlanStep {
+    id: i64,
+    /// Id of the enclosing step, 0 for the top level steps
+    parent: i64,
+    /// E.g. `SEARCH o USING INDEX idx_orders_user_id (user_id=?)`
+    detail: String,
+}
+
+/// Show how SQLite executes `sql`, without running it. Parameters (`?1`) are left unbound.
+#[ic_cdk::query]
+fn explain(sql: String) -> Result<Vec<PlanStep>> {
+    Ok(with_connection(|conn| {
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}"))?;
+
+        let steps = stmt.query_map([], |row| {
+            Ok(PlanStep {
+                id: row.get(0)?,
+                parent: row.get(1)?,
+                detail: row.get(3)?,
+            })
+        })?;
+
+        steps.collect::<ic_rusqlite::Result<Vec<_>>>()
+    })?)
+}
+
+mod benches {
+    use super::*;
+    use crate::benches::SELECT_WITH_JOIN;
+    use crate::{create_indices, execute};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    fn uses_index(plan: &[PlanStep], index: &str) -> bool {
+        plan.iter().any(|step| step.detail.contains(index))
+    }
+
+    // a missing index turns the join into a scan of all orders per user,
+    // the plans do not depend on the rows, so the tables stay empty
+    #[bench(raw)]
+    fn bench_explain_select_with_join() -> BenchResult {
+        create_indices();
+
+        let mut plan = vec![];
+
+        let result = bench_fn(|| {
+            plan = explain(SELECT_WITH_JOIN.to_string()).unwrap();
+        });
+
+        assert!(uses_index(&plan, "idx_orders_user_id"), "{plan:?}");
+
+        // the check notices the regression
+        execute("DROP INDEX idx_orders_user_id");
+        let plan = explain(SELECT_WITH_JOIN.to_string()).unwrap();
+        assert!(!uses_index(&plan, "idx_orders_user_id"), "{plan:?}");
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_explain_user_by_email() -> BenchResult {
+        create_indices();
+
+        bench_fn(|| {
+            let plan = explain("SELECT * FROM users WHERE email = ?1".to_string()).unwrap();
+            assert!(uses_index(&plan, "idx_users_email"), "{plan:?}");
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_explain_invalid_sql() -> BenchResult {
+        bench_fn(|| {
+            assert!(explain("SELECT * FROM missing_table".to_string()).is_err());
+        })
+    }
+}
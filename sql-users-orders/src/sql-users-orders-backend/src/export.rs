@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::Error;
+use crate::Result;
+use crate::CHUNK_SIZE;
+use crate::DB_FILENAME;
+
+/// Description of the frozen database file.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportInfo {
+    /// File size in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+thread_local! {
+    static EXPORT: RefCell<Option<ExportInfo>> = const { RefCell::new(None) };
+}
+
+/// Guard for controller-only endpoints.
+pub fn caller_is_controller() -> std::result::Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::api::msg_caller()) {
+        Ok(())
+    } else {
+        Err("only controllers can call this method".to_string())
+    }
+}
+
+/// Guard for endpoints modifying the database, rejects calls while an export is in progress.
+pub fn database_is_writable() -> std::result::Result<(), String> {
+    EXPORT.with_borrow(|export| match export {
+        Some(_) => Err("database is frozen for export, call end_export first".to_string()),
+        None => Ok(()),
+    })
+}
+
+pub fn sha256_file(path: &str) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    let size = std::io::copy(&mut file, &mut hasher)?;
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// Open the connection with `query_only`, so not even an unguarded statement can modify the frozen file.
+fn set_query_only(enabled: bool) {
+    let mut config = ic_rusqlite::get_connection_config();
+
+    if enabled {
+        config
+            .pragma_settings
+            .insert("query_only".to_string(), "ON".to_string());
+    } else {
+        config.pragma_settings.remove("query_only");
+    }
+
+    ic_rusqlite::set_connection_config(config);
+
+    // flush and release the database file, the pragmas are applied when the connection is opened again
+    ic_rusqlite::close_connection();
+}
+
+/// Freeze the database and describe the file to download.
+///
+/// Mutating endpoints are rejected until `end_export` is called.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn begin_export() -> Result<ExportInfo> {
+    if let Some(info) = EXPORT.with_borrow(|export| export.clone()) {
+        return Ok(info);
+    }
+
+    set_query_only(true);
+
+    let (size, sha256) = sha256_file(DB_FILENAME).map_err(|err| Error::CanisterError {
+        message: format!("{err:?}"),
+    })?;
+
+    let info = ExportInfo { size, sha256 };
+
+    EXPORT.with_borrow_mut(|export| *export = Some(info.clone()));
+
+    Ok(info)
+}
+
+/// Read the next chunk of the frozen database starting at `offset`, an empty chunk marks the end of the file.
+#[ic_cdk::query(guard = "caller_is_controller")]
+fn export_chunk(offset: u64) -> Result<Vec<u8>> {
+    let info = EXPORT
+        .with_borrow(|export| export.clone())
+        .ok_or(Error::CanisterError {
+            message: "no export in progress, call begin_export first".to_string(),
+        })?;
+
+    if offset >= info.size {
+        return Ok(Vec::new());
+    }
+
+    let read = || -> std::io::Result<Vec<u8>> {
+        let mut file = File::open(DB_FILENAME)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+        file.take(CHUNK_SIZE as u64).read_to_end(&mut buffer)?;
+
+        Ok(buffer)
+    };
+
+    read().map_err(|err| Error::CanisterError {
+        message: format!("{err:?}"),
+    })
+}
+
+/// Unfreeze the database.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn end_export() {
+    if EXPORT.with_borrow_mut(|export| export.take()).is_some() {
+        set_query_only(false);
+    }
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_orders, add_users, create_indices, execute_with_params, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    #[bench(raw)]
+    fn bench_export_database() -> BenchResult {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+
+        let mut info = None;
+        let mut downloaded = Vec::new();
+
+        let result = bench_fn(|| {
+            info = Some(begin_export().unwrap());
+
+            loop {
+                let chunk = export_chunk(downloaded.len() as u64).unwrap();
+                if chunk.is_empty() {
+                    break;
+                }
+                downloaded.extend_from_slice(&chunk);
+            }
+        });
+
+        let info = info.unwrap();
+
+        // the download matches the file described by `begin_export`
+        assert_eq!(downloaded.len() as u64, info.size);
+        assert_eq!(hex::encode(Sha256::digest(&downloaded)), info.sha256);
+
+        // writes are rejected while the export is in progress, by the guard and by the connection
+        assert!(database_is_writable().is_err());
+        assert!(execute_with_params(
+            "INSERT INTO users (username, email) VALUES (?1, ?2)".to_string(),
+            vec![
+                SqlValue::Text("late".to_string()),
+                SqlValue::Text("late@example.com".to_string()),
+            ],
+        )
+        .is_err());
+        assert_eq!(sha256_file(DB_FILENAME).unwrap(), (info.size, info.sha256));
+
+        // reads still work
+        query("SELECT COUNT(*) FROM users".to_string()).unwrap();
+
+        end_export();
+        assert!(database_is_writable().is_ok());
+        execute_with_params(
+            "INSERT INTO users (username, email) VALUES ('after', 'after@example.com')".to_string(),
+            vec![],
+        )
+        .unwrap();
+
+        result
+    }
+}
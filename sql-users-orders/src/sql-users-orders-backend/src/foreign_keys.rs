@@ -0,0 +1,286 @@
+use ic_rusqlite::with_connection;
+use ic_rusqlite::OptionalExtension;
+
+use crate::execute;
+use crate::export::caller_is_controller;
+use crate::export::database_is_writable;
+use crate::migrations::migrate;
+use crate::migrations::MIGRATIONS;
+use crate::search::DROP_USERS_FTS;
+use crate::Error;
+use crate::Result;
+
+const FOREIGN_KEYS: &str = "foreign_keys";
+
+fn set_pragma(enabled: bool) {
+    let mut config = ic_rusqlite::get_connection_config();
+
+    config.pragma_settings.insert(
+        FOREIGN_KEYS.to_string(),
+        if enabled { "ON" } else { "OFF" }.to_string(),
+    );
+
+    ic_rusqlite::set_connection_config(config);
+
+    // the pragmas are applied when the connection is opened again
+    ic_rusqlite::close_connection();
+}
+
+/// Apply the stored foreign key setting to the connection configuration.
+pub fn apply_foreign_keys_setting() {
+    let enabled = with_connection(|conn| {
+        conn.query_row(
+            "SELECT value FROM canister_settings WHERE name = ?1",
+            [FOREIGN_KEYS],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+    })
+    .expect("failed to read the canister settings");
+
+    if let Some(value) = enabled {
+        set_pragma(value == "ON");
+    }
+}
+
+/// Enable or disable enforcing the `orders.user_id` foreign key.
+///
+/// The SQLite build of `ic-rusqlite` enforces foreign keys by default (`SQLITE_DEFAULT_FOREIGN_KEYS=1`),
+/// the stored setting overrides it. Enabling fails if existing orders already refer to missing users.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn set_foreign_keys(enabled: bool) -> Result<()> {
+    if enabled {
+        let violations: i64 = with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })
+        })?;
+
+        if violations > 0 {
+            return Err(Error::CanisterError {
+                message: format!("{violations} rows violate foreign key constraints"),
+            });
+        }
+    }
+
+    with_connection(|conn| {
+        conn.execute(
+            "INSERT OR REPLACE INTO canister_settings (name, value) VALUES (?1, ?2)",
+            [FOREIGN_KEYS, if enabled { "ON" } else { "OFF" }],
+        )
+    })?;
+
+    set_pragma(enabled);
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn foreign_keys_enabled() -> Result<bool> {
+    Ok(with_connection(|conn| {
+        conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+    })?)
+}
+
+/// Rebuild of the orders table declaring the foreign key `ON DELETE CASCADE`, SQLite cannot change a foreign key in place.
+///
+/// Not a migration: only `recreate_tables(true)` runs it, so it must not take a version number of `MIGRATIONS`.
+const ORDERS_ON_DELETE_CASCADE: &str = "
+    CREATE TABLE orders_cascade (
+        order_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id INTEGER NOT NULL,
+        amount REAL NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE
+    );
+
+    INSERT INTO orders_cascade SELECT * FROM orders;
+    DROP TABLE orders;
+    ALTER TABLE orders_cascade RENAME TO orders;
+";
+
+/// Drop the users and orders and create them again with the migrations, with `cascade` deleting a user deletes its orders.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn recreate_tables(cascade: bool) -> Result<()> {
+    with_connection(|conn| {
+        conn.execute_batch(DROP_USERS_FTS)?;
+        conn.execute_batch(
+            "
+            DROP INDEX IF EXISTS idx_orders_user_id;
+            DROP INDEX IF EXISTS idx_users_email;
+            DROP TABLE IF EXISTS orders;
+            DROP TABLE IF EXISTS users;
+            ",
+        )?;
+
+        // the migrations start again from an empty database
+        conn.pragma_update(None, "user_version", 0)
+    })?;
+
+    migrate(MIGRATIONS)?;
+
+    if cascade {
+        with_connection(|mut conn| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(ORDERS_ON_DELETE_CASCADE)?;
+            tx.commit()
+        })?;
+    }
+
+    Ok(())
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_orders, add_users, create_indices, execute_with_params, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    // users to delete, together with their orders
+    const DELETED_USERS: i64 = 1000;
+
+    fn count(sql: &str) -> i64 {
+        match query(sql.to_string()).unwrap().rows[0][0] {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    fn fill(foreign_keys: bool, cascade: bool) -> i64 {
+        set_foreign_keys(foreign_keys).unwrap();
+        recreate_tables(cascade).unwrap();
+
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+
+        count(&format!(
+            "SELECT COUNT(*) FROM orders WHERE user_id <= {DELETED_USERS}"
+        ))
+    }
+
+    fn check_deleted() {
+        assert_eq!(
+            count(&format!(
+                "SELECT COUNT(*) FROM users WHERE user_id <= {DELETED_USERS}"
+            )),
+            0
+        );
+        assert_eq!(
+            count(&format!(
+                "SELECT COUNT(*) FROM orders WHERE user_id <= {DELETED_USERS}"
+            )),
+            0
+        );
+    }
+
+    #[bench(raw)]
+    fn bench_delete_1000_users_with_orders_manually() -> BenchResult {
+        assert!(fill(false, false) > 0);
+
+        let result = bench_fn(|| {
+            execute("BEGIN TRANSACTION");
+            execute(&format!(
+                "DELETE FROM orders WHERE user_id <= {DELETED_USERS}"
+            ));
+            execute(&format!(
+                "DELETE FROM users WHERE user_id <= {DELETED_USERS}"
+            ));
+            execute("COMMIT");
+        });
+
+        check_deleted();
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_delete_1000_users_with_orders_cascade() -> BenchResult {
+        assert!(fill(true, true) > 0);
+
+        let result = bench_fn(|| {
+            execute(&format!(
+                "DELETE FROM users WHERE user_id <= {DELETED_USERS}"
+            ));
+        });
+
+        check_deleted();
+
+        result
+    }
+
+    // compare with `bench_add_orders`, which runs with the default foreign key checks
+    #[bench(raw)]
+    fn bench_add_orders_without_foreign_keys() -> BenchResult {
+        set_foreign_keys(false).unwrap();
+        add_users(0, COUNT / 10).unwrap();
+
+        bench_fn(|| {
+            add_orders(0, COUNT, COUNT / 10).unwrap();
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_orphan_orders_rejected() -> BenchResult {
+        let insert_order = |user_id: i64| {
+            execute_with_params(
+                "INSERT INTO orders (user_id, amount) VALUES (?1, ?2)".to_string(),
+                vec![SqlValue::Integer(user_id), SqlValue::Real(10.0)],
+            )
+        };
+
+        add_users(0, 10).unwrap();
+
+        // the constraint is enforced by default
+        assert!(foreign_keys_enabled().unwrap());
+        assert!(insert_order(11).is_err());
+
+        // orphans are accepted once the checks are disabled
+        set_foreign_keys(false).unwrap();
+        assert!(!foreign_keys_enabled().unwrap());
+        insert_order(11).unwrap();
+
+        // foreign keys cannot be enabled while an orphan exists
+        assert!(set_foreign_keys(true).is_err());
+        execute("DELETE FROM orders WHERE user_id = 11");
+
+        set_foreign_keys(true).unwrap();
+        assert!(foreign_keys_enabled().unwrap());
+
+        let result = bench_fn(|| {
+            assert!(insert_order(11).is_err());
+        });
+
+        insert_order(1).unwrap();
+
+        // a user with orders cannot be deleted without the cascade
+        assert!(
+            execute_with_params("DELETE FROM users WHERE user_id = 1".to_string(), vec![]).is_err()
+        );
+
+        // the setting survives reopening the connection
+        ic_rusqlite::close_connection();
+        apply_foreign_keys_setting();
+        assert!(foreign_keys_enabled().unwrap());
+
+        // with the cascade, the orders are deleted together with the user
+        recreate_tables(true).unwrap();
+
+        // the rebuild is not a migration, the version stays at the last one
+        let version: u32 =
+            with_connection(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0)))
+                .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as u32);
+
+        add_users(0, 10).unwrap();
+        insert_order(1).unwrap();
+        assert!(insert_order(11).is_err());
+
+        execute("DELETE FROM users WHERE user_id = 1");
+        assert_eq!(count("SELECT COUNT(*) FROM orders"), 0);
+
+        result
+    }
+}
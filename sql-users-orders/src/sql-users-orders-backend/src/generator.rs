@@ -0,0 +1,508 @@
+//! Deterministic synthetic data for the users/orders schema.
+//!
+//! Every row is derived from the seed and the row number only, so the same rows are produced
+//! whether they are inserted in one call, in several calls, or by a background job.
+
+/// Seed used by `add_users`, `add_orders` and the insert jobs.
+pub const DEFAULT_SEED: u64 = 0x5EED_F00D;
+
+/// Skew of the orders-per-user distribution, 1.0 is the classic Zipf law.
+const ZIPF_EXPONENT: f64 = 1.0;
+
+// all timestamps fall into the year 2024
+const EPOCH_START: i64 = 1_704_067_200; // 2024-01-01 00:00:00 UTC
+const EPOCH_LENGTH: i64 = 366 * 24 * 3600;
+
+// log-normal amounts with a median of 40.00
+const AMOUNT_MEDIAN: f64 = 40.0;
+const AMOUNT_SIGMA: f64 = 1.0;
+const AMOUNT_MAX: f64 = 10_000.0;
+
+// random streams, so user and order values of the same row number are independent
+const USER_STREAM: u64 = 1;
+const ORDER_STREAM: u64 = 2;
+
+// Zipf ranks are scrambled into user ids by multiplying with about n / golden ratio,
+// so the most active users are spread over the id range
+const GOLDEN_RATIO: f64 = 0.618_033_988_749_895;
+
+const FIRST_NAMES: &[&str] = &[
+    "james",
+    "mary",
+    "john",
+    "patricia",
+    "robert",
+    "jennifer",
+    "michael",
+    "linda",
+    "william",
+    "elizabeth",
+    "david",
+    "barbara",
+    "richard",
+    "susan",
+    "joseph",
+    "jessica",
+    "thomas",
+    "sarah",
+    "charles",
+    "karen",
+    "daniel",
+    "nancy",
+    "matthew",
+    "lisa",
+    "anthony",
+    "betty",
+    "mark",
+    "sandra",
+    "paul",
+    "ashley",
+    "steven",
+    "emily",
+    "andrew",
+    "donna",
+    "kenneth",
+    "michelle",
+    "joshua",
+    "carol",
+    "kevin",
+    "amanda",
+    "brian",
+    "melissa",
+    "george",
+    "deborah",
+    "timothy",
+    "stephanie",
+    "ronald",
+    "rebecca",
+    "jason",
+    "laura",
+    "yuki",
+    "mateo",
+    "amara",
+    "lukas",
+    "priya",
+    "wei",
+    "olga",
+    "omar",
+    "sofia",
+    "noah",
+    "ingrid",
+    "kofi",
+    "elena",
+    "hiro",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "smith",
+    "johnson",
+    "williams",
+    "brown",
+    "jones",
+    "garcia",
+    "miller",
+    "davis",
+    "rodriguez",
+    "martinez",
+    "hernandez",
+    "lopez",
+    "gonzalez",
+    "wilson",
+    "anderson",
+    "thomas",
+    "taylor",
+    "moore",
+    "jackson",
+    "martin",
+    "lee",
+    "perez",
+    "thompson",
+    "white",
+    "harris",
+    "sanchez",
+    "clark",
+    "ramirez",
+    "lewis",
+    "robinson",
+    "walker",
+    "young",
+    "allen",
+    "king",
+    "wright",
+    "scott",
+    "torres",
+    "nguyen",
+    "hill",
+    "flores",
+    "green",
+    "adams",
+    "nelson",
+    "baker",
+    "hall",
+    "rivera",
+    "campbell",
+    "mitchell",
+    "carter",
+    "roberts",
+    "kowalski",
+    "tanaka",
+    "muller",
+    "novak",
+    "silva",
+    "kim",
+    "ivanov",
+    "haddad",
+    "okafor",
+    "larsen",
+    "rossi",
+    "dubois",
+    "sato",
+    "chen",
+];
+
+const DOMAINS: &[&str] = &[
+    "example.com",
+    "mail.example.org",
+    "inbox.example.net",
+    "corp.example.com",
+];
+
+/// A small PRNG (SplitMix64), seeded per row.
+struct Rng(u64);
+
+impl Rng {
+    fn for_row(seed: u64, stream: u64, n: u64) -> Self {
+        let mut rng = Rng(seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+        rng.0 ^= rng.next_u64() ^ n;
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n)
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Standard normal (Box-Muller)
+    fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Zipf distribution over ranks `1..=n` sampled by rejection-inversion (Hörmann, Derflinger),
+/// it needs no tables, so it works for any number of users.
+struct Zipf {
+    n: f64,
+    s: f64,
+    h_integral_x1: f64,
+    h_integral_n: f64,
+    threshold: f64,
+}
+
+impl Zipf {
+    fn new(n: u64, s: f64) -> Self {
+        let mut zipf = Zipf {
+            n: n as f64,
+            s,
+            h_integral_x1: 0.0,
+            h_integral_n: 0.0,
+            threshold: 0.0,
+        };
+
+        zipf.h_integral_x1 = zipf.h_integral(1.5) - 1.0;
+        zipf.h_integral_n = zipf.h_integral(zipf.n + 0.5);
+        zipf.threshold = 2.0 - zipf.h_integral_inv(zipf.h_integral(2.5) - zipf.h(2.0));
+
+        zipf
+    }
+
+    fn h(&self, x: f64) -> f64 {
+        (-self.s * x.ln()).exp()
+    }
+
+    // (x^(1-s) - 1) / (1-s), continuous at s = 1
+    fn h_integral(&self, x: f64) -> f64 {
+        let ln_x = x.ln();
+        helper2((1.0 - self.s) * ln_x) * ln_x
+    }
+
+    fn h_integral_inv(&self, x: f64) -> f64 {
+        let t = (x * (1.0 - self.s)).max(-1.0);
+        (helper1(t) * x).exp()
+    }
+
+    fn sample(&self, rng: &mut Rng) -> u64 {
+        loop {
+            let u = self.h_integral_n + rng.next_f64() * (self.h_integral_x1 - self.h_integral_n);
+            let x = self.h_integral_inv(u);
+            let k = (x + 0.5).floor().clamp(1.0, self.n);
+
+            if k - x <= self.threshold || u >= self.h_integral(k + 0.5) - self.h(k) {
+                return k as u64;
+            }
+        }
+    }
+}
+
+// ln(1 + x) / x
+fn helper1(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.ln_1p() / x
+    } else {
+        1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+    }
+}
+
+// (exp(x) - 1) / x
+fn helper2(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.exp_m1() / x
+    } else {
+        1.0 + x * 0.5 * (1.0 + x / 3.0 * (1.0 + 0.25 * x))
+    }
+}
+
+/// Format seconds since the epoch the way SQLite's `CURRENT_TIMESTAMP` does: `YYYY-MM-DD HH:MM:SS`.
+fn format_timestamp(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // civil date from days since 1970-01-01 (H. Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratedUser {
+    pub username: String,
+    pub email: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratedOrder {
+    pub user_id: u64,
+    pub amount: f64,
+    pub created_at: String,
+}
+
+struct UserValues {
+    first: &'static str,
+    last: &'static str,
+    domain: &'static str,
+    created: i64,
+}
+
+fn user_values(seed: u64, n: u64) -> UserValues {
+    let mut rng = Rng::for_row(seed, USER_STREAM, n);
+
+    let first = rng.pick(FIRST_NAMES);
+    let last = rng.pick(LAST_NAMES);
+    let domain = rng.pick(DOMAINS);
+
+    // sign-ups slow down over the year, half of the users join in the first quarter
+    let t = rng.next_f64();
+    let created = EPOCH_START + (t * t * EPOCH_LENGTH as f64) as i64;
+
+    UserValues {
+        first,
+        last,
+        domain,
+        created,
+    }
+}
+
+/// The user number `n` (zero-based), stored with `user_id = n + 1`.
+pub fn user(seed: u64, n: u64) -> GeneratedUser {
+    let UserValues {
+        first,
+        last,
+        domain,
+        created,
+    } = user_values(seed, n);
+
+    // the row number keeps the names unique
+    let id = n + 1;
+
+    GeneratedUser {
+        username: format!("{first}_{last}{id}"),
+        email: format!("{first}.{last}{id}@{domain}"),
+        created_at: format_timestamp(created),
+    }
+}
+
+/// Generator of the orders placed by `user_count` users.
+///
+/// The Zipf distribution and the rank scrambling are set up once, so generating an order costs only the sampling.
+pub struct OrderGenerator {
+    seed: u64,
+    user_count: u64,
+    zipf: Zipf,
+    multiplier: u64,
+}
+
+impl OrderGenerator {
+    /// Returns `None` if there are no users to place the orders.
+    pub fn new(seed: u64, user_count: u64) -> Option<Self> {
+        if user_count == 0 {
+            return None;
+        }
+
+        let mut multiplier = ((user_count as f64 * GOLDEN_RATIO) as u64).max(1);
+        while gcd(multiplier, user_count) != 1 {
+            multiplier += 1;
+        }
+
+        Some(OrderGenerator {
+            seed,
+            user_count,
+            zipf: Zipf::new(user_count, ZIPF_EXPONENT),
+            multiplier,
+        })
+    }
+
+    /// The order number `n` (zero-based).
+    ///
+    /// Users order with Zipfian frequencies, amounts are log-normal and an order is never older than its user.
+    pub fn order(&self, n: u64) -> GeneratedOrder {
+        let mut rng = Rng::for_row(self.seed, ORDER_STREAM, n);
+
+        let rank = self.zipf.sample(&mut rng);
+        let user_id = self.scramble_rank(rank);
+
+        let amount = (AMOUNT_MEDIAN * (AMOUNT_SIGMA * rng.normal()).exp()).clamp(0.5, AMOUNT_MAX);
+        let amount = (amount * 100.0).round() / 100.0;
+
+        let user_created = user_values(self.seed, user_id - 1).created;
+
+        let created =
+            user_created + rng.below((EPOCH_START + EPOCH_LENGTH - user_created) as u64) as i64;
+
+        GeneratedOrder {
+            user_id,
+            amount,
+            created_at: format_timestamp(created),
+        }
+    }
+
+    // map a rank to a user id with a bijection of 1..=user_count
+    fn scramble_rank(&self, rank: u64) -> u64 {
+        let n = self.user_count;
+
+        // the most active user lands in the middle of the range
+        (((rank - 1) as u128 * self.multiplier as u128 + (n / 2) as u128) % n as u128) as u64 + 1
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+mod benches {
+    use super::*;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    #[bench(raw)]
+    fn bench_generate_1000000_orders() -> BenchResult {
+        let user_count = COUNT / 10;
+        let mut orders = Vec::with_capacity(COUNT as usize);
+
+        let result = bench_fn(|| {
+            let generator = OrderGenerator::new(DEFAULT_SEED, user_count).unwrap();
+            for n in 0..COUNT {
+                orders.push(generator.order(n));
+            }
+        });
+
+        // there are no orders without users
+        assert!(OrderGenerator::new(DEFAULT_SEED, 0).is_none());
+
+        assert_eq!(format_timestamp(EPOCH_START), "2024-01-01 00:00:00");
+        assert_eq!(
+            format_timestamp(1_709_164_800 + 3661),
+            "2024-02-29 01:01:01"
+        );
+
+        // rows depend only on the seed and the row number
+        let generator = OrderGenerator::new(DEFAULT_SEED, user_count).unwrap();
+        assert_eq!(orders[12345], generator.order(12345));
+        assert_eq!(user(DEFAULT_SEED, 42), user(DEFAULT_SEED, 42));
+        let other_seed = OrderGenerator::new(DEFAULT_SEED + 1, user_count).unwrap();
+        assert_ne!(orders[12345], other_seed.order(12345));
+
+        // Zipfian orders per user: the 1% most active users place most of the orders
+        let mut per_user = vec![0u64; user_count as usize + 1];
+        for o in &orders {
+            assert!((1..=user_count).contains(&o.user_id));
+            per_user[o.user_id as usize] += 1;
+        }
+        per_user.sort_unstable_by(|a, b| b.cmp(a));
+        let top: u64 = per_user[..(user_count / 100) as usize].iter().sum();
+        assert!(top > COUNT / 2, "top 1% users placed {top} orders");
+
+        // the hot users are spread over the id range
+        let low_ids = orders.iter().filter(|o| o.user_id < 1000).count() as u64;
+        assert!(low_ids < COUNT / 20, "{low_ids} orders by users below 1000");
+
+        // varied amounts with the configured median
+        let mut amounts: Vec<f64> = orders.iter().map(|o| o.amount).collect();
+        amounts.sort_unstable_by(f64::total_cmp);
+        let median = amounts[amounts.len() / 2];
+        assert!((35.0..45.0).contains(&median), "median amount {median}");
+        assert!(amounts[0] >= 0.5 && amounts[amounts.len() - 1] <= AMOUNT_MAX);
+
+        // spread timestamps, never before the user signed up
+        let mut timestamps: Vec<&str> = orders.iter().map(|o| o.created_at.as_str()).collect();
+        timestamps.sort_unstable();
+        timestamps.dedup();
+        assert!(timestamps.len() as u64 > COUNT * 9 / 10);
+
+        for (n, o) in orders.iter().enumerate().take(10000) {
+            let u = user(DEFAULT_SEED, o.user_id - 1);
+            assert!(
+                o.created_at >= u.created_at,
+                "order {n} is older than its user"
+            );
+        }
+
+        result
+    }
+}
@@ -0,0 +1,348 @@
+use std::time::Duration;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::types::Type;
+use ic_rusqlite::with_connection;
+use ic_rusqlite::Connection;
+use ic_rusqlite::OptionalExtension;
+
+use crate::database_is_writable;
+use crate::insert_order;
+use crate::insert_user;
+use crate::order_generator;
+use crate::Error;
+use crate::Result;
+use crate::INSERT_ORDER;
+use crate::INSERT_USER;
+
+/// Instructions one batch may spend on inserts, well below the 40B limit of a timer execution,
+/// so the commit and the progress update always fit.
+const BATCH_INSTRUCTIONS: u64 = 10_000_000_000;
+
+/// Delay before retrying a batch while the database is frozen for export.
+const FROZEN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum JobKind {
+    Users,
+    Orders { user_count: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct JobStatus {
+    job_id: u64,
+    kind: JobKind,
+    offset: u64,
+    count: u64,
+    /// Rows inserted so far
+    done: u64,
+    /// Committed batches so far
+    batches: u64,
+    /// Instructions spent on all batches
+    instructions: u64,
+    finished: bool,
+    /// The error stopping the job, if any
+    error: Option<String>,
+}
+
+fn load_job(conn: &Connection, job_id: u64) -> ic_rusqlite::Result<Option<JobStatus>> {
+    conn.query_row(
+        "SELECT kind, user_count, start_offset, count, done, batches, instructions, finished, error
+         FROM bulk_jobs WHERE job_id = ?1",
+        [job_id],
+        |row| {
+            let kind = match row.get::<_, String>(0)?.as_str() {
+                "users" => JobKind::Users,
+                "orders" => JobKind::Orders {
+                    user_count: row.get(1)?,
+                },
+                other => {
+                    return Err(ic_rusqlite::Error::FromSqlConversionFailure(
+                        0,
+                        Type::Text,
+                        format!("unknown job kind {other}").into(),
+                    ))
+                }
+            };
+
+            Ok(JobStatus {
+                job_id,
+                kind,
+                offset: row.get(2)?,
+                count: row.get(3)?,
+                done: row.get(4)?,
+                batches: row.get(5)?,
+                instructions: row.get(6)?,
+                finished: row.get(7)?,
+                error: row.get(8)?,
+            })
+        },
+    )
+    .optional()
+}
+
+fn create_job(kind: JobKind, offset: u64, count: u64) -> Result<u64> {
+    with_connection(|conn| {
+        let (name, user_count) = match kind {
+            JobKind::Users => ("users", 0),
+            JobKind::Orders { user_count } => ("orders", user_count),
+        };
+
+        conn.execute(
+            "INSERT INTO bulk_jobs (kind, user_count, start_offset, count) VALUES (?1, ?2, ?3, ?4)",
+            ic_rusqlite::params![name, user_count, offset, count],
+        )?;
+
+        Ok(conn.last_insert_rowid() as u64)
+    })
+}
+
+fn start_job(kind: JobKind, offset: u64, count: u64) -> Result<u64> {
+    let job_id = create_job(kind, offset, count)?;
+
+    schedule(job_id, Duration::ZERO);
+
+    Ok(job_id)
+}
+
+fn schedule(job_id: u64, delay: Duration) {
+    ic_cdk_timers::set_timer(delay, move || run_job(job_id));
+}
+
+// timer callback: run one batch and reschedule until the job is finished
+fn run_job(job_id: u64) {
+    if database_is_writable().is_err() {
+        schedule(job_id, FROZEN_RETRY_DELAY);
+        return;
+    }
+
+    match run_batch(job_id) {
+        Ok(true) => {}
+        Ok(false) => schedule(job_id, Duration::ZERO),
+        Err(err) => {
+            // the failed batch was rolled back, record the error and stop the job
+            let message = match err {
+                Error::CanisterError { message } => message,
+                err => format!("{err:?}"),
+            };
+
+            let recorded = with_connection(|conn| {
+                conn.execute(
+                    "UPDATE bulk_jobs SET finished = 1, error = ?2 WHERE job_id = ?1",
+                    ic_rusqlite::params![job_id, message],
+                )
+            });
+
+            // a trap would only roll back this timer execution, the job stops either way
+            if let Err(err) = recorded {
+                ic_cdk::eprintln!("failed to record the error of job {job_id}: {err:?}");
+            }
+        }
+    }
+}
+
+/// Insert rows of the job until its instruction budget is used up, returns `true` once the job is finished.
+///
+/// The rows and the job progress are committed in one transaction.
+fn run_batch(job_id: u64) -> Result<bool> {
+    let start = ic_cdk::api::instruction_counter();
+
+    with_connection(|mut conn| {
+        let tx = conn.transaction()?;
+
+        let job = load_job(&tx, job_id)?.ok_or(Error::CanisterError {
+            message: format!("job {job_id} not found"),
+        })?;
+
+        if job.finished {
+            return Ok(true);
+        }
+
+        let mut done = job.done;
+
+        {
+            let (mut stmt, orders) = match job.kind {
+                JobKind::Users => (tx.prepare_cached(INSERT_USER)?, None),
+                JobKind::Orders { user_count } => (
+                    tx.prepare_cached(INSERT_ORDER)?,
+                    Some(order_generator(user_count)?),
+                ),
+            };
+
+            while done < job.count
+                && ic_cdk::api::instruction_counter() - start < BATCH_INSTRUCTIONS
+            {
+                match &orders {
+                    None => insert_user(&mut stmt, job.offset + done)?,
+                    Some(orders) => insert_order(&mut stmt, orders, job.offset + done)?,
+                };
+
+                done += 1;
+            }
+        }
+
+        let finished = done >= job.count;
+
+        tx.execute(
+            "UPDATE bulk_jobs SET done = ?2, batches = batches + 1, instructions = instructions + ?3, finished = ?4
+             WHERE job_id = ?1",
+            ic_rusqlite::params![
+                job_id,
+                done,
+                ic_cdk::api::instruction_counter() - start,
+                finished
+            ],
+        )?;
+
+        tx.commit()?;
+
+        Ok(finished)
+    })
+}
+
+/// Number of jobs not finished yet, their timers keep writing into the database.
+pub fn unfinished_jobs() -> Result<u64> {
+    Ok(with_connection(|conn| {
+        conn.query_row(
+            "SELECT COUNT(*) FROM bulk_jobs WHERE finished = 0",
+            [],
+            |row| row.get(0),
+        )
+    })?)
+}
+
+/// Continue the unfinished jobs, the timers are lost on upgrade.
+pub fn resume_jobs() {
+    // an unreadable job is skipped, trapping here would fail the whole upgrade
+    let jobs = with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT job_id FROM bulk_jobs WHERE finished = 0")?;
+        let ids = stmt.query_map([], |row| row.get::<_, u64>(0))?;
+
+        Ok::<_, ic_rusqlite::Error>(ids.collect::<Vec<_>>())
+    });
+
+    let jobs = match jobs {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            ic_cdk::eprintln!("failed to read the unfinished jobs: {err:?}");
+            return;
+        }
+    };
+
+    for job in jobs {
+        match job {
+            Ok(job_id) => schedule(job_id, Duration::ZERO),
+            Err(err) => ic_cdk::eprintln!("failed to read an unfinished job: {err:?}"),
+        }
+    }
+}
+
+/// Start inserting `count` users in the background, returns the job id.
+#[ic_cdk::update(guard = "database_is_writable")]
+fn start_add_users(offset: u64, count: u64) -> Result<u64> {
+    start_job(JobKind::Users, offset, count)
+}
+
+/// Start inserting `count` orders placed by `user_count` users in the background, returns the job id.
+#[ic_cdk::update(guard = "database_is_writable")]
+fn start_add_orders(offset: u64, count: u64, user_count: u64) -> Result<u64> {
+    order_generator(user_count)?;
+
+    start_job(JobKind::Orders { user_count }, offset, count)
+}
+
+#[ic_cdk::query]
+fn job_status(job_id: u64) -> Result<JobStatus> {
+    with_connection(|conn| {
+        load_job(&conn, job_id)?.ok_or(Error::CanisterError {
+            message: format!("job {job_id} not found"),
+        })
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_users, execute, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    fn count_rows(table: &str) -> i64 {
+        match query(format!("SELECT COUNT(*) FROM {table}")).unwrap().rows[0][0] {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    // the jobs are created without timers, the batches run directly
+    fn run_to_completion(job_id: u64) -> u64 {
+        let mut batches = 1;
+        while !run_batch(job_id).unwrap() {
+            batches += 1;
+        }
+        batches
+    }
+
+    #[bench(raw)]
+    fn bench_add_orders_job() -> BenchResult {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+
+        // every order is placed by a user
+        assert!(start_add_orders(0, COUNT, 0).is_err());
+
+        let job_id = create_job(JobKind::Orders { user_count }, 0, COUNT).unwrap();
+
+        let result = bench_fn(|| {
+            run_to_completion(job_id);
+        });
+
+        let status = job_status(job_id).unwrap();
+
+        assert!(status.finished);
+        assert_eq!(status.error, None);
+        assert_eq!(status.done, COUNT);
+        assert_eq!(count_rows("orders"), COUNT as i64);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_add_users_job_matches_add_users() -> BenchResult {
+        // no job yet
+        assert!(job_status(1).is_err());
+
+        let job_id = create_job(JobKind::Users, 0, COUNT / 10).unwrap();
+
+        let result = bench_fn(|| {
+            run_to_completion(job_id);
+        });
+
+        assert!(job_status(job_id).unwrap().finished);
+
+        // the job inserts exactly the same rows as the single-message endpoint
+        let job_users =
+            query("SELECT username, email FROM users ORDER BY user_id".to_string()).unwrap();
+        execute("DELETE FROM users");
+        add_users(0, COUNT / 10).unwrap();
+        let users =
+            query("SELECT username, email FROM users ORDER BY user_id".to_string()).unwrap();
+
+        assert_eq!(job_users.rows, users.rows);
+
+        // a finished job is not continued
+        assert!(run_batch(job_id).unwrap());
+        assert_eq!(count_rows("users"), (COUNT / 10) as i64);
+
+        // a job of an unknown kind is an error, not a users job
+        execute(&format!(
+            "UPDATE bulk_jobs SET kind = 'products', finished = 0 WHERE job_id = {job_id}"
+        ));
+        assert!(job_status(job_id).is_err());
+        assert!(run_batch(job_id).is_err());
+
+        result
+    }
+}
@@ -1,58 +1,135 @@
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
-use std::io::Write;
-
 use candid::CandidType;
 use candid::Deserialize;
 
-use ic_rusqlite::types::Type;
+use ic_rusqlite::params_from_iter;
+use ic_rusqlite::types::ToSqlOutput;
+use ic_rusqlite::types::ValueRef;
+use ic_rusqlite::Params;
+use ic_rusqlite::Statement;
+use ic_rusqlite::ToSql;
 
 use ic_rusqlite::with_connection;
 
+mod analytics;
+mod consistency;
+mod cursor;
+mod explain;
+mod export;
+mod foreign_keys;
+mod generator;
+mod jobs;
+mod migrations;
+mod savepoints;
+mod search;
+mod upload;
+
+use export::database_is_writable;
+use generator::OrderGenerator;
+
 type Result<T = String, E = Error> = std::result::Result<T, E>;
 
-type QueryResult<T = Vec<Vec<Option<String>>>, E = Error> = std::result::Result<T, E>;
+type QueryResult<T = QueryOutput, E = Error> = std::result::Result<T, E>;
+
+/// A single SQLite value, one variant per storage class.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<ValueRef<'_>> for SqlValue {
+    fn from(value: ValueRef<'_>) -> Self {
+        match value {
+            ValueRef::Null => SqlValue::Null,
+            ValueRef::Integer(v) => SqlValue::Integer(v),
+            ValueRef::Real(v) => SqlValue::Real(v),
+            ValueRef::Text(v) => SqlValue::Text(String::from_utf8_lossy(v).into_owned()),
+            ValueRef::Blob(v) => SqlValue::Blob(v.to_vec()),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ColumnInfo {
+    name: String,
+    /// Declared type of the column, `None` for expressions
+    decl_type: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct QueryOutput {
+    columns: Vec<ColumnInfo>,
+    rows: Vec<Vec<SqlValue>>,
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> ic_rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            SqlValue::Null => ValueRef::Null,
+            SqlValue::Integer(v) => ValueRef::Integer(*v),
+            SqlValue::Real(v) => ValueRef::Real(*v),
+            SqlValue::Text(v) => ValueRef::Text(v.as_bytes()),
+            SqlValue::Blob(v) => ValueRef::Blob(v),
+        }))
+    }
+}
+
+fn collect_rows(stmt: &mut Statement, params: impl Params) -> QueryResult {
+    let columns = stmt
+        .columns()
+        .iter()
+        .map(|c| ColumnInfo {
+            name: c.name().to_string(),
+            decl_type: c.decl_type().map(str::to_string),
+        })
+        .collect();
+
+    let cnt = stmt.column_count();
+
+    let mut rows = stmt.query(params)?;
+
+    let mut res: Vec<Vec<SqlValue>> = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        let mut vec: Vec<SqlValue> = Vec::with_capacity(cnt);
+        for idx in 0..cnt {
+            vec.push(row.get_ref(idx)?.into());
+        }
+        res.push(vec)
+    }
+
+    Ok(QueryOutput { columns, rows: res })
+}
 
 #[ic_cdk::query]
 fn query(sql: String) -> QueryResult {
     with_connection(|conn| {
-        let mut stmt = conn.prepare(&sql).unwrap();
-        let cnt = stmt.column_count();
-
-        let mut rows = stmt.query([]).unwrap();
-
-        let mut res: Vec<Vec<Option<String>>> = Vec::new();
-
-        loop {
-            match rows.next() {
-                Ok(row) => match row {
-                    Some(row) => {
-                        let mut vec: Vec<Option<String>> = Vec::new();
-                        for idx in 0..cnt {
-                            let v = row.get_ref_unwrap(idx);
-                            match v.data_type() {
-                                Type::Null => vec.push(None),
-                                Type::Integer => vec.push(Some(v.as_i64().unwrap().to_string())),
-                                Type::Real => vec.push(Some(v.as_f64().unwrap().to_string())),
-                                Type::Text => vec.push(Some(v.as_str().unwrap().parse().unwrap())),
-                                Type::Blob => vec.push(Some(hex::encode(v.as_blob().unwrap()))),
-                            }
-                        }
-                        res.push(vec)
-                    }
-                    None => break,
-                },
-                Err(err) => {
-                    return Err(Error::CanisterError {
-                        message: format!("{err:?}"),
-                    })
-                }
-            }
-        }
-        Ok(res)
+        let mut stmt = conn.prepare(&sql)?;
+
+        collect_rows(&mut stmt, [])
+    })
+}
+
+/// Run a query with bound parameters, the prepared statement is kept in the statement cache.
+#[ic_cdk::query]
+fn query_with_params(sql: String, params: Vec<SqlValue>) -> QueryResult {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare_cached(&sql)?;
+
+        collect_rows(&mut stmt, params_from_iter(params.iter()))
+    })
+}
+
+/// Execute a statement with bound parameters, returns the number of changed rows.
+#[ic_cdk::update(guard = "database_is_writable")]
+fn execute_with_params(sql: String, params: Vec<SqlValue>) -> Result<u64> {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare_cached(&sql)?;
+
+        Ok(stmt.execute(params_from_iter(params.iter()))? as u64)
     })
 }
 
@@ -62,33 +139,13 @@ fn execute(sql: &str) {
     })
 }
 
-#[ic_cdk::update]
+/// Create the tables by applying the pending migrations.
+#[ic_cdk::update(guard = "database_is_writable")]
 fn create_tables() {
-    execute(
-        "
-        CREATE TABLE IF NOT EXISTS users (
-                        user_id INTEGER PRIMARY KEY AUTOINCREMENT,
-                        username TEXT NOT NULL,
-                        email TEXT NOT NULL,
-                        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                    )
-       ",
-    );
-
-    execute(
-        "
-            CREATE TABLE IF NOT EXISTS orders (
-                order_id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL,
-                amount REAL NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (user_id) REFERENCES users(user_id)
-            )
-        ",
-    );
+    migrations::run_migrations();
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "database_is_writable")]
 fn create_indices() {
     execute("CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);");
     execute("CREATE INDEX IF NOT EXISTS idx_orders_user_id ON orders(user_id);");
@@ -96,12 +153,18 @@ fn create_indices() {
 
 #[ic_cdk::init]
 fn init() {
-    create_tables();
+    migrations::run_migrations();
+
+    // the connection settings do not survive an upgrade
+    foreign_keys::apply_foreign_keys_setting();
 }
 
 #[ic_cdk::post_upgrade]
 fn post_upgrade() {
     init();
+
+    // timers do not survive an upgrade
+    jobs::resume_jobs();
 }
 
 #[derive(CandidType, Deserialize, Debug)]
@@ -110,86 +173,66 @@ enum Error {
     CanisterError { message: String },
 }
 
+impl From<ic_rusqlite::Error> for Error {
+    fn from(err: ic_rusqlite::Error) -> Self {
+        Error::CanisterError {
+            message: format!("{err:?}"),
+        }
+    }
+}
+
 const DB_FILENAME: &str = "./DB/main.db";
 const CHUNK_SIZE: usize = 2000000;
 
-// Basic implementation for downloading the database using the icml tool
-// The real implementation should keep canister in "service" mode to prevent database updates during download,
-// also make sure only the owner of the canister can call this method
-#[ic_cdk::query]
-fn db_download(offset: u64) -> Vec<u8> {
-    ic_rusqlite::close_connection();
-
-    let mut file = match File::open(DB_FILENAME) {
-        Ok(f) => f,
-        Err(_) => return Vec::new(),
-    };
-
-    // Get file length
-    let file_len = match file.metadata() {
-        Ok(meta) => meta.len(),
-        Err(_) => return Vec::new(),
-    };
-
-    if offset >= file_len {
-        return Vec::new();
-    }
+const INSERT_USER: &str = "insert into users (username, email, created_at) values (?, ?, ?)";
 
-    // Seek to the requested offset
-    if file.seek(SeekFrom::Start(offset)).is_err() {
-        return Vec::new();
-    }
+const INSERT_ORDER: &str = "insert into orders (user_id, amount, created_at) values (?, ?, ?)";
 
-    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
-    let mut handle = file.take(CHUNK_SIZE as u64);
+/// Insert the generated user number `n` (zero-based) using a prepared `INSERT_USER` statement.
+fn insert_user(stmt: &mut Statement, n: u64) -> ic_rusqlite::Result<usize> {
+    let user = generator::user(generator::DEFAULT_SEED, n);
 
-    if handle.read_to_end(&mut buffer).is_err() {
-        return Vec::new();
-    }
+    stmt.execute(ic_rusqlite::params![
+        user.username,
+        user.email,
+        user.created_at
+    ])
+}
 
-    buffer
+/// Generator of the orders placed by one of `user_count` users, fails if there are no users.
+fn order_generator(user_count: u64) -> Result<OrderGenerator> {
+    OrderGenerator::new(generator::DEFAULT_SEED, user_count).ok_or(Error::CanisterError {
+        message: "user_count must be positive, every order is placed by a user".to_string(),
+    })
 }
 
-// Basic implementation to upload the database using the icml tool
-// The real implementation should keep canister in "service" mode to prevent database updates during upload
-// also make sure only the owner of the canister can call this method
-#[ic_cdk::update]
-fn db_upload(offset: u64, content: Vec<u8>) {
-    ic_rusqlite::close_connection();
-
-    // open file for writing
-    if let Ok(mut file) = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true) // create file if it doesn't exist
-        .open(DB_FILENAME)
-    {
-        if file.seek(SeekFrom::Start(offset)).is_ok() {
-            // write bytes at given offset
-            let _ = file.write_all(&content);
-        }
-    }
+/// Insert the generated order number `n` (zero-based) using a prepared `INSERT_ORDER` statement.
+fn insert_order(
+    stmt: &mut Statement,
+    orders: &OrderGenerator,
+    n: u64,
+) -> ic_rusqlite::Result<usize> {
+    let order = orders.order(n);
+
+    stmt.execute(ic_rusqlite::params![
+        order.user_id,
+        order.amount,
+        order.created_at
+    ])
 }
 
-#[ic_cdk::update]
+#[ic_cdk::update(guard = "database_is_writable")]
 fn add_users(offset: u64, count: u64) -> Result {
     with_connection(|mut conn| {
         let tx = conn.transaction().unwrap();
 
-        let sql = String::from("insert into users (username, email) values (?, ?)");
-
         {
-            let mut stmt = tx.prepare_cached(&sql).unwrap();
+            let mut stmt = tx.prepare_cached(INSERT_USER).unwrap();
 
             let mut i = 0;
 
             while i < count {
-                let id = offset + i + 1;
-                let username = format!("user{id}");
-                let email = format!("user{id}@example.com");
-
-                stmt.execute(ic_rusqlite::params![username, email])
-                    .expect("insert of a user failed!");
+                insert_user(&mut stmt, offset + i).expect("insert of a user failed!");
 
                 i += 1;
             }
@@ -201,27 +244,23 @@ fn add_users(offset: u64, count: u64) -> Result {
     })
 }
 
-#[ic_cdk::update]
-fn add_orders(offset: u64, count: u64, id_mod: u64) -> Result {
+#[ic_cdk::update(guard = "database_is_writable")]
+fn add_orders(offset: u64, count: u64, user_count: u64) -> Result {
+    let orders = order_generator(user_count)?;
+
     with_connection(|mut conn| {
         let tx = conn.transaction().unwrap();
 
-        let sql = String::from("insert into orders (user_id, amount) values (?, ?)");
-
         {
-            let mut stmt = tx.prepare_cached(&sql).unwrap();
+            let mut stmt = tx.prepare_cached(INSERT_ORDER).unwrap();
 
             let mut i = 0;
 
             while i < count {
-                let id = (offset + i + 1) * 13 % id_mod + 1;
-
-                stmt.execute(ic_rusqlite::params![id, (id * 100 + id * 17) / 15])
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "insertion of a new order failed: i = {i} count = {count} id = {id}!"
-                        )
-                    });
+                insert_order(&mut stmt, &orders, offset + i).unwrap_or_else(|_| {
+                    let id = orders.order(offset + i).user_id;
+                    panic!("insertion of a new order failed: i = {i} count = {count} id = {id}!")
+                });
 
                 i += 1;
             }
@@ -239,6 +278,24 @@ mod benches {
 
     const COUNT: u64 = 1000000u64;
 
+    // the plan of this query is checked in `explain::benches`
+    pub(crate) const SELECT_WITH_JOIN: &str = "
+        SELECT u.user_id, u.username, o.order_id, o.amount
+        FROM users u
+        JOIN orders o ON u.user_id = o.user_id
+        WHERE u.user_id < 1000
+        ORDER BY o.created_at DESC;
+    ";
+
+    fn count_orders() -> i64 {
+        let res = query("SELECT COUNT(*) FROM orders".to_string()).unwrap();
+
+        match res.rows[0][0] {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
     #[bench(raw)]
     fn bench_add_users() -> BenchResult {
         bench_fn(|| {
@@ -250,6 +307,9 @@ mod benches {
     fn bench_add_orders() -> BenchResult {
         add_users(0, COUNT / 10).unwrap();
 
+        // every order is placed by a user
+        assert!(add_orders(0, 100, 0).is_err());
+
         bench_fn(|| {
             add_orders(0, COUNT, COUNT / 10).unwrap();
         })
@@ -272,17 +332,67 @@ mod benches {
         create_indices();
 
         bench_fn(|| {
-            query(
-                r#"
-                SELECT u.user_id, u.username, o.order_id, o.amount
-                FROM users u
-                JOIN orders o ON u.user_id = o.user_id
-                WHERE u.user_id < 1000
-                ORDER BY o.created_at DESC;
-                "#
-                .to_string(),
-            )
-            .unwrap();
+            query(SELECT_WITH_JOIN.to_string()).unwrap();
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_select_with_join_encoding() -> BenchResult {
+        add_users(0, COUNT / 10).unwrap();
+        add_orders(0, COUNT, COUNT / 10).unwrap();
+        create_indices();
+
+        let res = query(SELECT_WITH_JOIN.to_string());
+
+        bench_fn(|| {
+            candid::encode_one(&res).unwrap();
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_select_1000_users_by_id_literal() -> BenchResult {
+        add_users(0, COUNT / 10).unwrap();
+
+        bench_fn(|| {
+            for id in 1..=1000 {
+                query(format!(
+                    "SELECT username, email FROM users WHERE user_id = {id}"
+                ))
+                .unwrap();
+            }
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_select_1000_users_by_id_params() -> BenchResult {
+        add_users(0, COUNT / 10).unwrap();
+
+        bench_fn(|| {
+            for id in 1..=1000 {
+                query_with_params(
+                    "SELECT username, email FROM users WHERE user_id = ?1".to_string(),
+                    vec![SqlValue::Integer(id)],
+                )
+                .unwrap();
+            }
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_update_1000_orders_with_params() -> BenchResult {
+        add_users(0, COUNT / 10).unwrap();
+        add_orders(0, COUNT, COUNT / 10).unwrap();
+
+        bench_fn(|| {
+            for id in 1..=1000 {
+                let changed = execute_with_params(
+                    "UPDATE orders SET amount = ?1 WHERE order_id = ?2".to_string(),
+                    vec![SqlValue::Real(id as f64 / 2.0), SqlValue::Integer(id)],
+                )
+                .unwrap();
+
+                assert_eq!(changed, 1);
+            }
         })
     }
 
@@ -304,6 +414,33 @@ mod benches {
         })
     }
 
+    // unlike `user%`, the prefix matches the generated emails
+    #[bench(raw)]
+    fn bench_select_like_first_name_on_indexed_field() -> BenchResult {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+
+        let mut res = None;
+
+        let result = bench_fn(|| {
+            res = Some(
+                query(
+                    r#"
+                    SELECT * FROM users WHERE email LIKE 'james.%';
+                    "#
+                    .to_string(),
+                )
+                .unwrap(),
+            );
+        });
+
+        assert!(!res.unwrap().rows.is_empty());
+
+        result
+    }
+
     #[bench(raw)]
     fn bench_add_100_indexed_orders() -> BenchResult {
         let user_count = COUNT / 10;
@@ -323,9 +460,17 @@ mod benches {
         add_orders(0, COUNT, user_count).unwrap();
         create_indices();
 
-        bench_fn(|| {
-            execute("DELETE FROM orders WHERE user_id <= 100 ");
-        })
+        // the orders per user are Zipfian, so the 1000 orders of the lowest user ids are selected through the index
+        let result = bench_fn(|| {
+            execute(
+                "DELETE FROM orders WHERE order_id IN
+                    (SELECT order_id FROM orders ORDER BY user_id LIMIT 1000)",
+            );
+        });
+
+        assert_eq!(count_orders(), COUNT as i64 - 1000);
+
+        result
     }
 
     #[bench(raw)]
@@ -348,9 +493,7 @@ mod benches {
         add_orders(0, COUNT, user_count).unwrap();
         create_indices();
 
-        let res = query("SELECT COUNT(*) FROM orders".to_string()).unwrap();
-        let s = res[0][0].clone().unwrap();
-        let cnt: i64 = s.parse().expect("Not a valid number");
+        let cnt = count_orders();
 
         assert_eq!(cnt, 1000000);
 
@@ -358,18 +501,14 @@ mod benches {
             execute("BEGIN TRANSACTION");
             execute("DELETE FROM orders WHERE order_id > 900000");
 
-            let res = query("SELECT COUNT(*) FROM orders".to_string()).unwrap();
-            let s = res[0][0].clone().unwrap();
-            let cnt: i64 = s.parse().expect("Not a valid number");
+            let cnt = count_orders();
 
             assert_eq!(cnt, 900000);
 
             execute("ROLLBACK");
         });
 
-        let res = query("SELECT COUNT(*) FROM orders".to_string()).unwrap();
-        let s = res[0][0].clone().unwrap();
-        let cnt: i64 = s.parse().expect("Not a valid number");
+        let cnt = count_orders();
 
         assert_eq!(cnt, 1000000);
 
@@ -0,0 +1,272 @@
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+use ic_rusqlite::Connection;
+
+use crate::Error;
+use crate::Result;
+
+/// A schema change, applied once in the order of the versions.
+#[derive(Clone)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// The migrations of the canister, new migrations are appended with the next version number and never changed afterwards.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create users and orders",
+        // databases created before the migrations already have the tables
+        sql: "
+        CREATE TABLE IF NOT EXISTS users (
+            user_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            email TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS orders (
+            order_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            amount REAL NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (user_id) REFERENCES users(user_id)
+        );
+    ",
+    },
+    Migration {
+        version: 2,
+        description: "create the bulk insert jobs",
+        // the progress of a job is committed together with its inserted rows and survives upgrades,
+        // databases created before the migrations may already have the table
+        sql: "
+        CREATE TABLE IF NOT EXISTS bulk_jobs (
+            job_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            user_count INTEGER NOT NULL,
+            start_offset INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            done INTEGER NOT NULL DEFAULT 0,
+            batches INTEGER NOT NULL DEFAULT 0,
+            instructions INTEGER NOT NULL DEFAULT 0,
+            finished INTEGER NOT NULL DEFAULT 0,
+            error TEXT
+        );
+    ",
+    },
+    Migration {
+        version: 3,
+        description: "create the canister settings",
+        // settings applied to the connection when it opens, kept in the database so they survive upgrades
+        sql: "
+        CREATE TABLE IF NOT EXISTS canister_settings (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+    ",
+    },
+];
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct MigrationInfo {
+    version: u32,
+    description: String,
+    applied: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct SchemaVersion {
+    /// `PRAGMA user_version` of the database
+    version: u32,
+    migrations: Vec<MigrationInfo>,
+}
+
+fn user_version(conn: &Connection) -> ic_rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// The versions must be 1, 2, 3, ... in order, a gap or a reordering would skip migrations on some databases.
+fn check_versions(migrations: &[Migration]) -> Result<()> {
+    for (i, migration) in migrations.iter().enumerate() {
+        if migration.version != i as u32 + 1 {
+            return Err(Error::CanisterError {
+                message: format!(
+                    "migration \"{}\" has version {}, expected {}",
+                    migration.description,
+                    migration.version,
+                    i + 1
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the migrations newer than the database version, returns the number of applied migrations.
+///
+/// Each migration runs in its own transaction together with the update of `PRAGMA user_version`,
+/// so a failing migration leaves the database at the previous version.
+pub fn migrate(migrations: &[Migration]) -> Result<usize> {
+    check_versions(migrations)?;
+
+    with_connection(|mut conn| {
+        let current = user_version(&conn)?;
+        let mut applied = 0;
+
+        for migration in migrations.iter().filter(|m| m.version > current) {
+            let tx = conn.transaction()?;
+
+            tx.execute_batch(migration.sql)?;
+            tx.pragma_update(None, "user_version", migration.version)?;
+
+            tx.commit()?;
+
+            applied += 1;
+        }
+
+        Ok(applied)
+    })
+}
+
+/// Apply the pending migrations, traps if one of them fails, so a failing upgrade is rolled back.
+pub fn run_migrations() {
+    if let Err(err) = migrate(MIGRATIONS) {
+        ic_cdk::trap(format!("database migration failed: {err:?}"));
+    }
+}
+
+#[ic_cdk::query]
+fn schema_version() -> Result<SchemaVersion> {
+    let version = with_connection(|conn| user_version(&conn))?;
+
+    Ok(SchemaVersion {
+        version,
+        migrations: MIGRATIONS
+            .iter()
+            .map(|m| MigrationInfo {
+                version: m.version,
+                description: m.description.to_string(),
+                applied: m.version <= version,
+            })
+            .collect(),
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_orders, add_users, create_indices, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    fn count(sql: &str) -> i64 {
+        match query(sql.to_string()).unwrap().rows[0][0] {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    // the canister migrations followed by the one under test
+    fn with_migrations(next: Migration) -> Vec<Migration> {
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(next);
+        migrations
+    }
+
+    #[bench(raw)]
+    fn bench_migrate_1000000_orders_add_column_and_backfill() -> BenchResult {
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+
+        // init applied all canister migrations
+        let version = schema_version().unwrap();
+        assert_eq!(version.version, MIGRATIONS.len() as u32);
+        assert!(version.migrations.iter().all(|m| m.applied));
+        assert_eq!(migrate(MIGRATIONS).unwrap(), 0);
+
+        let migrations = with_migrations(Migration {
+            version: MIGRATIONS.len() as u32 + 1,
+            description: "store order amounts in cents",
+            sql: "
+                ALTER TABLE orders ADD COLUMN amount_cents INTEGER;
+                UPDATE orders SET amount_cents = CAST(round(amount * 100) AS INTEGER);
+            ",
+        });
+
+        let result = bench_fn(|| {
+            assert_eq!(migrate(&migrations).unwrap(), 1);
+        });
+
+        assert_eq!(
+            with_connection(|conn| user_version(&conn)).unwrap(),
+            MIGRATIONS.len() as u32 + 1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM orders WHERE amount_cents IS NULL"),
+            0
+        );
+
+        // migrations are applied only once
+        assert_eq!(migrate(&migrations).unwrap(), 0);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_failed_migration_is_rolled_back() -> BenchResult {
+        add_users(0, 1000).unwrap();
+
+        let migrations = with_migrations(Migration {
+            version: MIGRATIONS.len() as u32 + 1,
+            description: "fails halfway",
+            sql: "
+                ALTER TABLE users ADD COLUMN nickname TEXT;
+                UPDATE users SET nickname = username;
+                INSERT INTO missing_table VALUES (1);
+            ",
+        });
+
+        let result = bench_fn(|| {
+            assert!(migrate(&migrations).is_err());
+        });
+
+        // the versions must be contiguous and increasing
+        let mut gap = MIGRATIONS.to_vec();
+        gap.push(Migration {
+            version: MIGRATIONS.len() as u32 + 2,
+            description: "skips a version",
+            sql: "ALTER TABLE users ADD COLUMN nickname TEXT;",
+        });
+        assert!(migrate(&gap).is_err());
+
+        let mut reordered = MIGRATIONS.to_vec();
+        reordered.insert(
+            0,
+            Migration {
+                version: MIGRATIONS.len() as u32 + 1,
+                description: "comes too early",
+                sql: "ALTER TABLE users ADD COLUMN nickname TEXT;",
+            },
+        );
+        assert!(migrate(&reordered).is_err());
+
+        // neither the column nor the version were changed
+        assert_eq!(
+            with_connection(|conn| user_version(&conn)).unwrap(),
+            MIGRATIONS.len() as u32
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM pragma_table_info('users') WHERE name = 'nickname'"),
+            0
+        );
+
+        result
+    }
+}
@@ -0,0 +1,178 @@
+//! Savepoint benches, each scenario runs under the journal modes supporting rollback.
+//!
+//! `OFF` cannot roll back and `WAL` needs shared memory, so they are not measured.
+
+mod benches {
+    use ic_rusqlite::with_connection;
+
+    use crate::{
+        add_orders, add_users, create_indices, execute, insert_order, order_generator, query,
+        SqlValue, INSERT_ORDER,
+    };
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    // orders deleted by each step
+    const DELETED: i64 = 100000;
+
+    // requests in the per-request atomicity scenario, every tenth one fails
+    const REQUESTS: u64 = 1000;
+
+    fn count_orders() -> i64 {
+        match query("SELECT COUNT(*) FROM orders".to_string())
+            .unwrap()
+            .rows[0][0]
+        {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    fn prepare(journal_mode: &str) {
+        let mut config = ic_rusqlite::get_connection_config();
+        config
+            .pragma_settings
+            .insert("journal_mode".to_string(), journal_mode.to_string());
+        ic_rusqlite::set_connection_config(config);
+        ic_rusqlite::close_connection();
+
+        let effective: String =
+            with_connection(|conn| conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)))
+                .unwrap();
+        assert_eq!(effective, journal_mode.to_lowercase());
+
+        let user_count = COUNT / 10;
+        add_users(0, user_count).unwrap();
+        add_orders(0, COUNT, user_count).unwrap();
+        create_indices();
+    }
+
+    // returns the number of deleted orders
+    fn delete_orders(from: i64) -> usize {
+        with_connection(|conn| {
+            conn.execute(
+                "DELETE FROM orders WHERE order_id > ?1 AND order_id <= ?2",
+                [from, from + DELETED],
+            )
+        })
+        .unwrap()
+    }
+
+    /// SAVEPOINT a { delete; SAVEPOINT b { delete; SAVEPOINT c { delete } RELEASE c } ROLLBACK TO b; RELEASE b } RELEASE a
+    fn bench_nested_savepoints(journal_mode: &str) -> BenchResult {
+        prepare(journal_mode);
+
+        let result = bench_fn(|| {
+            execute("SAVEPOINT a");
+            delete_orders(0);
+
+            execute("SAVEPOINT b");
+            delete_orders(DELETED);
+
+            execute("SAVEPOINT c");
+            delete_orders(2 * DELETED);
+            execute("RELEASE c");
+
+            // undoes the deletes of b and c
+            execute("ROLLBACK TO b");
+            execute("RELEASE b");
+
+            execute("RELEASE a");
+        });
+
+        assert_eq!(count_orders(), COUNT as i64 - DELETED);
+
+        result
+    }
+
+    /// BEGIN; delete; SAVEPOINT s; delete; ROLLBACK TO s; COMMIT
+    fn bench_partial_rollback(journal_mode: &str) -> BenchResult {
+        prepare(journal_mode);
+
+        let mut rolled_back = 0;
+
+        let result = bench_fn(|| {
+            execute("BEGIN TRANSACTION");
+            delete_orders(0);
+
+            execute("SAVEPOINT s");
+            rolled_back = delete_orders(DELETED);
+            execute("ROLLBACK TO s");
+
+            execute("COMMIT");
+        });
+
+        // the savepoint deleted orders, but only the delete before it was committed
+        assert_eq!(rolled_back as i64, DELETED);
+        assert_eq!(count_orders(), COUNT as i64 - DELETED);
+
+        result
+    }
+
+    /// One transaction, each request runs in its own `Transaction::savepoint`, failed requests are rolled back.
+    fn bench_savepoint_per_request(journal_mode: &str) -> BenchResult {
+        prepare(journal_mode);
+
+        let orders = order_generator(COUNT / 10).unwrap();
+
+        let result = bench_fn(|| {
+            with_connection(|mut conn| {
+                let mut tx = conn.transaction().unwrap();
+
+                for request in 0..REQUESTS {
+                    let sp = tx.savepoint().unwrap();
+
+                    {
+                        let mut stmt = sp.prepare_cached(INSERT_ORDER).unwrap();
+                        insert_order(&mut stmt, &orders, COUNT + request).unwrap();
+                        insert_order(&mut stmt, &orders, COUNT + REQUESTS + request).unwrap();
+                    }
+
+                    if request % 10 == 0 {
+                        // dropping a savepoint rolls it back
+                        drop(sp);
+                    } else {
+                        sp.commit().unwrap();
+                    }
+                }
+
+                tx.commit().unwrap();
+            })
+        });
+
+        assert_eq!(
+            count_orders(),
+            COUNT as i64 + 2 * (REQUESTS - REQUESTS / 10) as i64
+        );
+
+        result
+    }
+
+    // one bench per scenario and journal mode
+    macro_rules! journal_mode_benches {
+        ($($name:ident => $scenario:ident($journal_mode:literal),)*) => {
+            $(
+                #[bench(raw)]
+                fn $name() -> BenchResult {
+                    $scenario($journal_mode)
+                }
+            )*
+        };
+    }
+
+    journal_mode_benches! {
+        bench_nested_savepoints_delete => bench_nested_savepoints("DELETE"),
+        bench_nested_savepoints_truncate => bench_nested_savepoints("TRUNCATE"),
+        bench_nested_savepoints_persist => bench_nested_savepoints("PERSIST"),
+        bench_nested_savepoints_memory => bench_nested_savepoints("MEMORY"),
+        bench_partial_rollback_delete => bench_partial_rollback("DELETE"),
+        bench_partial_rollback_truncate => bench_partial_rollback("TRUNCATE"),
+        bench_partial_rollback_persist => bench_partial_rollback("PERSIST"),
+        bench_partial_rollback_memory => bench_partial_rollback("MEMORY"),
+        bench_savepoint_per_request_delete => bench_savepoint_per_request("DELETE"),
+        bench_savepoint_per_request_truncate => bench_savepoint_per_request("TRUNCATE"),
+        bench_savepoint_per_request_persist => bench_savepoint_per_request("PERSIST"),
+        bench_savepoint_per_request_memory => bench_savepoint_per_request("MEMORY"),
+    }
+}
@@ -0,0 +1,210 @@
+use ic_rusqlite::with_connection;
+
+use crate::collect_rows;
+use crate::export::caller_is_controller;
+use crate::export::database_is_writable;
+use crate::QueryResult;
+use crate::Result;
+
+/// Full-text index of the usernames and emails, kept in sync with `users` by triggers.
+///
+/// The index stores no copy of the text (`content='users'`), it reads the columns from `users` when needed.
+const CREATE_USERS_FTS: &str = "
+    CREATE VIRTUAL TABLE IF NOT EXISTS users_fts USING fts5(
+        username, email, content='users', content_rowid='user_id'
+    );
+
+    CREATE TRIGGER IF NOT EXISTS users_fts_insert AFTER INSERT ON users BEGIN
+        INSERT INTO users_fts(rowid, username, email) VALUES (new.user_id, new.username, new.email);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS users_fts_delete AFTER DELETE ON users BEGIN
+        INSERT INTO users_fts(users_fts, rowid, username, email) VALUES ('delete', old.user_id, old.username, old.email);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS users_fts_update AFTER UPDATE OF username, email ON users BEGIN
+        INSERT INTO users_fts(users_fts, rowid, username, email) VALUES ('delete', old.user_id, old.username, old.email);
+        INSERT INTO users_fts(rowid, username, email) VALUES (new.user_id, new.username, new.email);
+    END;
+
+    -- index the users inserted before the index existed
+    INSERT INTO users_fts(users_fts) VALUES ('rebuild');
+";
+
+pub const DROP_USERS_FTS: &str = "
+    DROP TRIGGER IF EXISTS users_fts_insert;
+    DROP TRIGGER IF EXISTS users_fts_delete;
+    DROP TRIGGER IF EXISTS users_fts_update;
+    DROP TABLE IF EXISTS users_fts;
+";
+
+/// Create the full-text index of the users and index the existing users.
+///
+/// The index is opt-in, its triggers add to the cost of every insert, update and delete in `users`.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn create_users_fts() -> Result<()> {
+    Ok(with_connection(|conn| {
+        conn.execute_batch(CREATE_USERS_FTS)
+    })?)
+}
+
+/// Drop the full-text index of the users together with its triggers.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn drop_users_fts() -> Result<()> {
+    Ok(with_connection(|conn| conn.execute_batch(DROP_USERS_FTS))?)
+}
+
+/// Find up to `limit` users by an FTS5 query on their username and email, best matches first.
+///
+/// The query uses the [FTS5 syntax](https://sqlite.org/fts5.html#full_text_query_syntax), e.g. `kowalski*` or `email: mary AND corp`.
+#[ic_cdk::query]
+fn search_users(fts_query: String, limit: u64) -> QueryResult {
+    with_connection(|conn| {
+        let mut stmt = conn.prepare_cached(
+            "
+            SELECT u.user_id, u.username, u.email
+            FROM users_fts f
+            JOIN users u ON u.user_id = f.rowid
+            WHERE users_fts MATCH ?1
+            ORDER BY f.rank
+            LIMIT ?2
+            ",
+        )?;
+
+        collect_rows(&mut stmt, ic_rusqlite::params![fts_query, limit])
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_users, create_indices, execute, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    const COUNT: u64 = 1000000u64;
+
+    fn count(sql: &str) -> i64 {
+        match query(sql.to_string()).unwrap().rows[0][0] {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    fn fill_users(fts: bool) {
+        add_users(0, COUNT / 10).unwrap();
+        create_indices();
+
+        if fts {
+            create_users_fts().unwrap();
+        }
+    }
+
+    #[bench(raw)]
+    fn bench_search_users_like_substring() -> BenchResult {
+        fill_users(false);
+
+        let mut found = 0;
+
+        let result = bench_fn(|| {
+            found = query(
+                "SELECT user_id, username, email FROM users WHERE email LIKE '%kowalski%'"
+                    .to_string(),
+            )
+            .unwrap()
+            .rows
+            .len();
+        });
+
+        assert!(found > 0);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_search_users_fts_match() -> BenchResult {
+        fill_users(true);
+
+        let mut found = 0;
+
+        let result = bench_fn(|| {
+            found = search_users("email: kowalski*".to_string(), COUNT / 10)
+                .unwrap()
+                .rows
+                .len();
+        });
+
+        // the index finds the same users as the scan
+        assert_eq!(
+            found as i64,
+            count("SELECT COUNT(*) FROM users WHERE email LIKE '%kowalski%'")
+        );
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_search_users_like_prefix() -> BenchResult {
+        fill_users(false);
+
+        bench_fn(|| {
+            query(
+                "SELECT user_id, username, email FROM users WHERE email LIKE 'james.%' LIMIT 100"
+                    .to_string(),
+            )
+            .unwrap();
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_search_users_fts_prefix() -> BenchResult {
+        fill_users(true);
+
+        bench_fn(|| {
+            let res = search_users("email: james*".to_string(), 100).unwrap();
+            assert_eq!(res.rows.len(), 100);
+        })
+    }
+
+    // compare with `bench_add_users`, here the triggers update the index
+    #[bench(raw)]
+    fn bench_add_users_with_fts() -> BenchResult {
+        create_users_fts().unwrap();
+
+        bench_fn(|| {
+            add_users(0, COUNT / 10).unwrap();
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_fts_follows_updates_and_deletes() -> BenchResult {
+        // no index unless it is created
+        assert!(search_users("zebediah".to_string(), 1000).is_err());
+
+        create_users_fts().unwrap();
+        add_users(0, 1000).unwrap();
+
+        let matches = |fts_query: &str| {
+            search_users(fts_query.to_string(), 1000)
+                .unwrap()
+                .rows
+                .len()
+        };
+
+        let result = bench_fn(|| {
+            execute("UPDATE users SET username = 'zebediah_quux' WHERE user_id = 7");
+        });
+
+        assert_eq!(matches("zebediah"), 1);
+
+        execute("DELETE FROM users WHERE user_id = 7");
+        assert_eq!(matches("zebediah"), 0);
+
+        // the index stays consistent with the content table
+        execute("INSERT INTO users_fts(users_fts, rank) VALUES ('integrity-check', 1)");
+
+        drop_users_fts().unwrap();
+        assert!(search_users("zebediah".to_string(), 1000).is_err());
+        execute("DELETE FROM users WHERE user_id = 8");
+
+        result
+    }
+}
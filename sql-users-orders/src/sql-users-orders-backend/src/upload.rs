@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use crate::export::caller_is_controller;
+use crate::export::database_is_writable;
+use crate::export::sha256_file;
+use crate::foreign_keys::apply_foreign_keys_setting;
+use crate::jobs::resume_jobs;
+use crate::jobs::unfinished_jobs;
+use crate::migrations::run_migrations;
+use crate::Error;
+use crate::Result;
+use crate::DB_FILENAME;
+
+/// Staging file receiving the uploaded chunks.
+const UPLOAD_FILENAME: &str = "./DB/upload.db";
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+struct Upload {
+    size: u64,
+    sha256: String,
+    /// Received byte ranges, start -> end (exclusive), never overlapping or adjacent
+    received: BTreeMap<u64, u64>,
+}
+
+impl Upload {
+    fn add_range(&mut self, mut start: u64, mut end: u64) {
+        // merge with the ranges overlapping or touching [start, end)
+        let touching: Vec<(u64, u64)> = self
+            .received
+            .range(..=end)
+            .filter(|(_, &e)| e >= start)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+
+        for (s, e) in touching {
+            self.received.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+
+        self.received.insert(start, end);
+    }
+
+    fn missing(&self) -> Vec<(u64, u64)> {
+        let mut missing = Vec::new();
+        let mut pos = 0;
+
+        for (&s, &e) in &self.received {
+            if s > pos {
+                missing.push((pos, s));
+            }
+            pos = e;
+        }
+
+        if pos < self.size {
+            missing.push((pos, self.size));
+        }
+
+        missing
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct UploadStatus {
+    size: u64,
+    received: u64,
+    /// Byte ranges [start, end) that were not uploaded yet
+    missing: Vec<(u64, u64)>,
+}
+
+thread_local! {
+    static UPLOAD: RefCell<Option<Upload>> = const { RefCell::new(None) };
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::CanisterError {
+        message: format!("{err:?}"),
+    }
+}
+
+fn no_upload() -> Error {
+    Error::CanisterError {
+        message: "no upload in progress, call begin_upload first".to_string(),
+    }
+}
+
+/// Start a new upload of a database file with the given size and hex-encoded SHA-256, any previous upload is discarded.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn begin_upload(size: u64, sha256: String) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(UPLOAD_FILENAME)
+        .map_err(io_error)?;
+
+    file.set_len(size).map_err(io_error)?;
+
+    UPLOAD.with_borrow_mut(|upload| {
+        *upload = Some(Upload {
+            size,
+            sha256: sha256.to_lowercase(),
+            received: BTreeMap::new(),
+        })
+    });
+
+    Ok(())
+}
+
+/// Write a chunk at the given offset, chunks may arrive in any order and may be sent again.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn upload_chunk(offset: u64, content: Vec<u8>) -> Result<()> {
+    UPLOAD.with_borrow_mut(|upload| {
+        let upload = upload.as_mut().ok_or_else(no_upload)?;
+
+        let end = offset
+            .checked_add(content.len() as u64)
+            .filter(|&end| end <= upload.size)
+            .ok_or_else(|| Error::CanisterError {
+                message: format!(
+                    "chunk of {} bytes at offset {offset} exceeds the file size {}",
+                    content.len(),
+                    upload.size
+                ),
+            })?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(UPLOAD_FILENAME)
+            .map_err(io_error)?;
+
+        file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        file.write_all(&content).map_err(io_error)?;
+
+        upload.add_range(offset, end);
+
+        Ok(())
+    })
+}
+
+#[ic_cdk::query(guard = "caller_is_controller")]
+fn upload_status() -> Result<UploadStatus> {
+    UPLOAD.with_borrow(|upload| {
+        let upload = upload.as_ref().ok_or_else(no_upload)?;
+
+        let missing = upload.missing();
+
+        Ok(UploadStatus {
+            size: upload.size,
+            received: upload.size - missing.iter().map(|(s, e)| e - s).sum::<u64>(),
+            missing,
+        })
+    })
+}
+
+fn verify(upload: &Upload) -> Result<()> {
+    let missing = upload.missing();
+    if !missing.is_empty() {
+        return Err(Error::CanisterError {
+            message: format!("upload is incomplete, missing ranges: {missing:?}"),
+        });
+    }
+
+    let (_, sha256) = sha256_file(UPLOAD_FILENAME).map_err(io_error)?;
+    if sha256 != upload.sha256 {
+        return Err(Error::CanisterError {
+            message: format!(
+                "checksum mismatch: expected {}, received {sha256}",
+                upload.sha256
+            ),
+        });
+    }
+
+    let mut header = [0u8; 16];
+    File::open(UPLOAD_FILENAME)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(io_error)?;
+
+    if &header != SQLITE_HEADER {
+        return Err(Error::CanisterError {
+            message: "the uploaded file is not an SQLite database".to_string(),
+        });
+    }
+
+    let conn = ic_rusqlite::Connection::open(UPLOAD_FILENAME)?;
+    let check: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    conn.close().map_err(|(_, err)| err)?;
+
+    if check != "ok" {
+        return Err(Error::CanisterError {
+            message: format!("integrity check failed: {check}"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Verify the uploaded file and replace the database with it.
+///
+/// The database file is memory-mounted, so the staged file is copied over it instead of being renamed.
+/// A message either completes or is rolled back entirely, so the replacement is still atomic.
+/// Refused while a job is unfinished, its timers would continue in the replaced database.
+#[ic_cdk::update(guard = "caller_is_controller", guard = "database_is_writable")]
+fn finish_upload() -> Result<()> {
+    let jobs = unfinished_jobs()?;
+    if jobs > 0 {
+        return Err(Error::CanisterError {
+            message: format!(
+                "{jobs} jobs are not finished, wait for them before replacing the database"
+            ),
+        });
+    }
+
+    UPLOAD.with_borrow_mut(|upload| {
+        verify(upload.as_ref().ok_or_else(no_upload)?)?;
+
+        ic_rusqlite::close_connection();
+
+        let mut source = File::open(UPLOAD_FILENAME).map_err(io_error)?;
+        let mut target = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(DB_FILENAME)
+            .map_err(io_error)?;
+
+        std::io::copy(&mut source, &mut target).map_err(io_error)?;
+        drop(target);
+
+        std::fs::remove_file(UPLOAD_FILENAME).map_err(io_error)?;
+        *upload = None;
+
+        // reopen the connection on the new database and bring it to the current schema,
+        // a failing migration traps and rolls back the replacement
+        ic_rusqlite::with_connection(|conn| conn.query_row("SELECT 1", [], |_| Ok(())))?;
+        run_migrations();
+
+        // the same as after an upgrade: the settings and the jobs of the uploaded database
+        apply_foreign_keys_setting();
+        resume_jobs();
+
+        Ok(())
+    })
+}
+
+/// Discard the current upload.
+#[ic_cdk::update(guard = "caller_is_controller")]
+fn abort_upload() {
+    UPLOAD.with_borrow_mut(|upload| {
+        if upload.take().is_some() {
+            let _ = std::fs::remove_file(UPLOAD_FILENAME);
+        }
+    });
+}
+
+mod benches {
+    use super::*;
+    use crate::migrations::MIGRATIONS;
+    use crate::{add_users, execute, query, SqlValue};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    use sha2::Digest;
+    use sha2::Sha256;
+
+    // small chunks, so the database is uploaded in many pieces
+    const TEST_CHUNK_SIZE: usize = 64 * 1024;
+
+    fn count_users() -> i64 {
+        match query("SELECT COUNT(*) FROM users".to_string())
+            .unwrap()
+            .rows[0][0]
+        {
+            SqlValue::Integer(cnt) => cnt,
+            ref v => panic!("Not a valid number: {v:?}"),
+        }
+    }
+
+    #[bench(raw)]
+    fn bench_upload_database() -> BenchResult {
+        add_users(0, 100000).unwrap();
+
+        // an older database, the migrations are applied again after the upload
+        execute("PRAGMA user_version = 0");
+
+        ic_rusqlite::close_connection();
+        let db = std::fs::read(DB_FILENAME).unwrap();
+        let sha256 = hex::encode(Sha256::digest(&db));
+        let users = count_users();
+
+        execute("DELETE FROM users WHERE user_id > 1000");
+        assert_ne!(count_users(), users);
+
+        // a wrong checksum is rejected
+        begin_upload(db.len() as u64, "00".repeat(32)).unwrap();
+        // chunks past the end are rejected, also when the end overflows
+        assert!(upload_chunk(db.len() as u64, vec![0]).is_err());
+        assert!(upload_chunk(u64::MAX, vec![0]).is_err());
+        for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate() {
+            upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec()).unwrap();
+        }
+        assert!(finish_upload().is_err());
+
+        // the database is not replaced under a running job
+        execute("INSERT INTO bulk_jobs (kind, user_count, start_offset, count) VALUES ('users', 0, 0, 1)");
+        begin_upload(db.len() as u64, sha256.clone()).unwrap();
+        for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate() {
+            upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec()).unwrap();
+        }
+        assert!(finish_upload().is_err());
+        execute("UPDATE bulk_jobs SET finished = 1");
+
+        let result = bench_fn(|| {
+            begin_upload(db.len() as u64, sha256.clone()).unwrap();
+
+            // send the chunks in reverse order, the first one twice
+            upload_chunk(0, db[..TEST_CHUNK_SIZE].to_vec()).unwrap();
+            for (i, chunk) in db.chunks(TEST_CHUNK_SIZE).enumerate().rev() {
+                upload_chunk((i * TEST_CHUNK_SIZE) as u64, chunk.to_vec()).unwrap();
+            }
+
+            assert!(upload_status().unwrap().missing.is_empty());
+
+            finish_upload().unwrap();
+        });
+
+        // the database was replaced with the uploaded one and migrated
+        assert_eq!(count_users(), users);
+        let version: u32 = ic_rusqlite::with_connection(|conn| {
+            conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        })
+        .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as u32);
+
+        result
+    }
+}
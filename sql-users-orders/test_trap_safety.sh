@@ -0,0 +1,76 @@
+#!/bin/bash
+
+# Checks that the database stays consistent after an update traps in the middle of a transaction.
+# Reinstalls the canister with the `trap-testing` feature, all its data is lost: dfx deploy && ./test_trap_safety.sh
+
+set -e
+
+backend=sql-users-orders-backend
+
+# `add_orders_trap_at` only exists in a build with the `trap-testing` feature
+cargo build --release --target wasm32-wasip1 -p $backend --features trap-testing
+wasi2ic target/wasm32-wasip1/release/sql_users_orders_backend.wasm target/wasm32-wasip1/release/nowasi-trap-testing.wasm
+dfx canister install $backend --mode reinstall --yes --wasm target/wasm32-wasip1/release/nowasi-trap-testing.wasm
+
+export USER_COUNT=10000:nat64
+export ORDER_COUNT=100000:nat64
+
+dfx canister call $backend add_users "(0:nat64, $USER_COUNT)"
+dfx canister call $backend add_orders "(0:nat64, $ORDER_COUNT, $USER_COUNT)"
+
+check() {
+  dfx canister call $backend check_database
+}
+
+# insert orders and trap after `trap_at` rows of the open transaction
+trap_at() {
+  local count=$1
+  local trap_at=$2
+
+  echo "trap after $trap_at of $count orders"
+
+  if dfx canister call --candid src/$backend/trap-testing.did $backend add_orders_trap_at "(100000:nat64, $count:nat64, $USER_COUNT, $trap_at:nat64)"; then
+    echo "FAILED: add_orders_trap_at did not trap"
+    exit 1
+  fi
+}
+
+before=$(check)
+echo "$before"
+
+if ! echo "$before" | grep -q 'integrity_check = "ok"'; then
+  echo "FAILED: integrity check before the trap"
+  exit 1
+fi
+
+trap_at 10000 5000
+
+# a transaction larger than the existing table
+trap_at 500000 400000
+
+after=$(check)
+echo "$after"
+
+# no rows, no AUTOINCREMENT gaps, no damaged pages
+if [ "$before" != "$after" ]; then
+  echo "FAILED: the database changed after the trap"
+  exit 1
+fi
+
+# the cached connection still works on the next call
+dfx canister call $backend add_orders "(100000:nat64, 1000:nat64, $USER_COUNT)"
+
+next=$(check)
+echo "$next"
+
+if ! echo "$next" | grep -q 'orders = 101_000'; then
+  echo "FAILED: orders were not added after the trap"
+  exit 1
+fi
+
+if ! echo "$next" | grep -q 'integrity_check = "ok"'; then
+  echo "FAILED: integrity check after the trap"
+  exit 1
+fi
+
+echo "OK"
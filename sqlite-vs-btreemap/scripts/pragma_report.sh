@@ -0,0 +1,48 @@
+#!/bin/bash
+
+# Collect the pragma matrix results from the canbench report into a single markdown table.
+# Run `canbench --persist` first, then launch this script from the project folder: ./scripts/pragma_report.sh
+
+set -e
+
+results=${1:-canbench_results.yml}
+
+if [[ ! -f "$results" ]]; then
+    echo "Error: File '$results' does not exist, run 'canbench --persist' first." >&2
+    exit 1
+fi
+
+echo "journal_mode | synchronous | page_size | cache_size | add_00001 | add_00100 | add_10000 | total"
+echo "-------------|-------------|-----------|------------|-----------|-----------|-----------|------"
+
+awk '
+    /^  [^ ]/ {
+        name = $1; sub(":$", "", name)
+        if (name !~ /^bench_pragma_/) name = ""
+        section = ""
+        next
+    }
+
+    name == "" { next }
+
+    /^    total:/  { section = "total"; next }
+    /^    scopes:/ { section = "scopes"; next }
+
+    section == "scopes" && /^      [^ ]/ { scope = $1; sub(":$", "", scope); next }
+
+    /instructions:/ {
+        if (section == "total") {
+            total[name] = $2
+        } else {
+            value[name, scope] = $2
+        }
+    }
+
+    END {
+        for (name in total) {
+            split(substr(name, length("bench_pragma_") + 1), p, "_")
+            printf "%s | %s | %s | %s | %s | %s | %s | %s\n", toupper(p[1]), toupper(p[2]), p[3], p[4],
+                value[name, "add_00001"], value[name, "add_00100"], value[name, "add_10000"], total[name]
+        }
+    }
+' "$results" | sort -t '|' -k 8 -n
@@ -0,0 +1,183 @@
+use ic_rusqlite::params_from_iter;
+use ic_rusqlite::with_connection;
+use ic_rusqlite::ToSql;
+
+use crate::for_each_user;
+use crate::PAYLOAD;
+
+const INSERT_USER: &str = "insert into users (id, username) values (?, ?);";
+
+// default `SQLITE_MAX_VARIABLE_NUMBER`, the most parameters a statement can bind
+const MAX_VARIABLES: u64 = 32766;
+
+// parameters bound per row of `add_users_sqlite_multirow`
+const COLUMNS: u64 = 2;
+
+fn user_ids(offset: u64, increment: u64, count: u64) -> Vec<u64> {
+    let mut ids = Vec::new();
+    for_each_user(offset, increment, count, |id| ids.push(id));
+    ids
+}
+
+/// Insert users without an explicit transaction, SQLite commits each row separately.
+#[ic_cdk::update]
+fn add_users_sqlite_per_row(offset: u64, increment: u64, count: u64) {
+    PAYLOAD.with_borrow(|payload| {
+        with_connection(|conn| {
+            let mut stmt = conn.prepare_cached(INSERT_USER).unwrap();
+
+            for_each_user(offset, increment, count, |id| {
+                stmt.execute((id, payload))
+                    .expect("insert of a user failed!");
+            });
+        })
+    })
+}
+
+/// Insert users committing a transaction after every `batch_size` rows.
+#[ic_cdk::update]
+fn add_users_sqlite_batched(offset: u64, increment: u64, count: u64, batch_size: u64) {
+    let ids = user_ids(offset, increment, count);
+
+    PAYLOAD.with_borrow(|payload| {
+        with_connection(|mut conn| {
+            for batch in ids.chunks(batch_size.max(1) as usize) {
+                let tx = conn.transaction().unwrap();
+
+                {
+                    let mut stmt = tx.prepare_cached(INSERT_USER).unwrap();
+
+                    for id in batch {
+                        stmt.execute((id, payload))
+                            .expect("insert of a user failed!");
+                    }
+                }
+
+                tx.commit().expect("COMMIT USER INSERTION FAILED!");
+            }
+        })
+    })
+}
+
+/// Insert users with multi-row `INSERT ... VALUES (...),(...)` statements of `rows_per_statement` rows, all in one transaction.
+///
+/// `rows_per_statement` is clamped to the rows whose parameters fit into one statement.
+#[ic_cdk::update]
+fn add_users_sqlite_multirow(offset: u64, increment: u64, count: u64, rows_per_statement: u64) {
+    let ids = user_ids(offset, increment, count);
+    let rows_per_statement = rows_per_statement.clamp(1, MAX_VARIABLES / COLUMNS);
+
+    PAYLOAD.with_borrow(|payload| {
+        with_connection(|mut conn| {
+            let tx = conn.transaction().unwrap();
+
+            for rows in ids.chunks(rows_per_statement as usize) {
+                let values = vec!["(?, ?)"; rows.len()].join(", ");
+                let sql = format!("insert into users (id, username) values {values};");
+
+                let mut stmt = tx.prepare_cached(&sql).unwrap();
+
+                let params = rows
+                    .iter()
+                    .flat_map(|id| [id as &dyn ToSql, payload as &dyn ToSql]);
+
+                stmt.execute(params_from_iter(params))
+                    .expect("insert of users failed!");
+            }
+
+            tx.commit().expect("COMMIT USER INSERTION FAILED!");
+        })
+    })
+}
+
+/// Insert users by generating the SQL text with literal values and running it with `execute_batch`.
+#[ic_cdk::update]
+fn add_users_sqlite_execute_batch(offset: u64, increment: u64, count: u64) {
+    PAYLOAD.with_borrow(|payload| {
+        let username = payload.replace('\'', "''");
+
+        let mut sql = String::from("BEGIN;");
+        for_each_user(offset, increment, count, |id| {
+            sql.push_str(&format!(
+                "insert into users (id, username) values ({id}, '{username}');"
+            ));
+        });
+        sql.push_str("COMMIT;");
+
+        with_connection(|conn| {
+            conn.execute_batch(&sql)
+                .expect("batch insertion of users failed!");
+        })
+    })
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_users_sqlite, create_tables, init_payload};
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    // initial count of inserted elements
+    const INITIAL_COUNT: u64 = 1000000u64;
+    // do insertions in the middle of the existing element set
+    const OFFSET: u64 = INITIAL_COUNT / 2 + 5;
+    // size of an element being inserted
+    const PAYLOAD_SIZE: usize = 100usize;
+
+    fn bench_insert(f: impl FnOnce()) -> BenchResult {
+        init_payload(PAYLOAD_SIZE);
+        create_tables();
+
+        add_users_sqlite(0, 10, INITIAL_COUNT);
+
+        bench_fn(f)
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_per_row_add_01000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_per_row(OFFSET, 10, 1000))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_per_row_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_per_row(OFFSET, 10, 10000))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_batched_0010_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_batched(OFFSET, 10, 10000, 10))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_batched_0100_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_batched(OFFSET, 10, 10000, 100))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_multirow_0010_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_multirow(OFFSET, 10, 10000, 10))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_multirow_0100_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_multirow(OFFSET, 10, 10000, 100))
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_multirow_max_add_20000() -> BenchResult {
+        let result = bench_insert(|| add_users_sqlite_multirow(OFFSET, 10, 20000, u64::MAX));
+
+        // the rows are split into two statements below the parameter limit
+        let count: u64 = with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+                .unwrap()
+        });
+        assert_eq!(count, INITIAL_COUNT / 10 + 20000);
+
+        result
+    }
+
+    #[bench(raw)]
+    fn bench_sqlite_execute_batch_add_10000() -> BenchResult {
+        bench_insert(|| add_users_sqlite_execute_batch(OFFSET, 10, 10000))
+    }
+}
@@ -1,25 +1,18 @@
-use candid::CandidType;
-use candid::Deserialize;
-
 use ic_rusqlite::with_connection;
 use ic_stable_structures::memory_manager::MemoryManager;
 use ic_stable_structures::memory_manager::VirtualMemory;
 use ic_stable_structures::BTreeMap;
 use ic_stable_structures::DefaultMemoryImpl;
-use ic_stable_structures::Memory;
 
 use ic_stable_structures::memory_manager::MemoryId;
 use std::cell::RefCell;
 
-#[derive(CandidType, Deserialize, Debug)]
-enum Error {
-    InvalidCanister,
-    CanisterError { message: String },
-}
-
-type Result<T = String, E = Error> = std::result::Result<T, E>;
+mod insert_modes;
+mod pragma_matrix;
+mod profiling;
+mod records;
 
-const PROFILING: MemoryId = MemoryId::new(50);
+use profiling::{profile, profiling_init, Method};
 
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
@@ -56,11 +49,6 @@ fn create_tables() {
     });
 }
 
-pub fn profiling_init() {
-    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(PROFILING));
-    memory.grow(4096);
-}
-
 #[ic_cdk::init]
 fn init() {
     profiling_init();
@@ -77,32 +65,36 @@ fn for_each_user(offset: u64, increment: u64, count: u64, mut f: impl FnMut(u64)
 
 #[ic_cdk::update]
 fn add_users_btree(offset: u64, increment: u64, count: u64) {
-    PAYLOAD.with_borrow(|payload| {
-        MAP.with_borrow_mut(|map| {
-            for_each_user(offset, increment, count, |id| {
-                map.insert(id, payload.clone());
-            });
+    profile(Method::AddUsersBtree, count, || {
+        PAYLOAD.with_borrow(|payload| {
+            MAP.with_borrow_mut(|map| {
+                for_each_user(offset, increment, count, |id| {
+                    map.insert(id, payload.clone());
+                });
+            })
         })
     })
 }
 
 #[ic_cdk::update]
 fn add_users_sqlite(offset: u64, increment: u64, count: u64) {
-    PAYLOAD.with_borrow(|payload| {
-        with_connection(|mut conn| {
-            let tx = conn.transaction().unwrap();
-            let sql = String::from("insert into users (id, username) values (?, ?);");
-
-            {
-                let mut stmt = tx.prepare_cached(&sql).unwrap();
-
-                for_each_user(offset, increment, count, |id| {
-                    stmt.execute((id, payload))
-                        .expect("insert of a user failed!");
-                });
-            }
-
-            tx.commit().expect("COMMIT USER INSERTION FAILED!");
+    profile(Method::AddUsersSqlite, count, || {
+        PAYLOAD.with_borrow(|payload| {
+            with_connection(|mut conn| {
+                let tx = conn.transaction().unwrap();
+                let sql = String::from("insert into users (id, username) values (?, ?);");
+
+                {
+                    let mut stmt = tx.prepare_cached(&sql).unwrap();
+
+                    for_each_user(offset, increment, count, |id| {
+                        stmt.execute((id, payload))
+                            .expect("insert of a user failed!");
+                    });
+                }
+
+                tx.commit().expect("COMMIT USER INSERTION FAILED!");
+            })
         })
     })
 }
@@ -254,227 +246,6 @@ mod benches {
         })
     }
 
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_00001() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 1);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_00010() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 10);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_00050() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 50);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_00100() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 100);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_01000() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 1000);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_memory_journal_add_10000() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "MEMORY".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 10000);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_00001() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 1);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_00010() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 10);
-        })
-    }
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_00050() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-        create_tables();
-
-        init_payload(PAYLOAD_SIZE);
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 50);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_00100() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 100);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_01000() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 1000);
-        })
-    }
-
-    #[bench(raw)]
-    fn bench_sqlite_no_journal_add_10000() -> BenchResult {
-        let mut config = ic_rusqlite::ConnectionConfig::new();
-
-        config
-            .pragma_settings
-            .insert("journal_mode".to_string(), "OFF".to_string());
-        ic_rusqlite::set_connection_config(config);
-
-        init_payload(PAYLOAD_SIZE);
-        create_tables();
-
-        add_users_sqlite(0, 10, INITIAL_COUNT);
-
-        bench_fn(|| {
-            add_users_sqlite(OFFSET, 10, 10000);
-        })
-    }
-
     #[bench(raw)]
     fn bench_read_users_btree() -> BenchResult {
         // Prepopulate
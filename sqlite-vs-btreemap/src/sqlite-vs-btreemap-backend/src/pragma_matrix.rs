@@ -0,0 +1,182 @@
+use ic_rusqlite::with_connection;
+
+/// A combination of pragma settings the database connection is opened with.
+#[derive(Clone, Copy, Debug)]
+pub struct PragmaConfig {
+    pub journal_mode: &'static str,
+    pub synchronous: &'static str,
+    pub page_size: u32,
+    pub cache_size: i64,
+}
+
+impl PragmaConfig {
+    /// Replace the connection configuration and reopen the database with the new settings.
+    ///
+    /// The connection applies its pragmas in the arbitrary order of a `HashMap`, but the page size can no longer
+    /// change once the journal is in `WAL` mode, so both are set here explicitly, `page_size` first.
+    pub fn apply(&self) {
+        let mut config = ic_rusqlite::ConnectionConfig::new();
+
+        let pragmas = &mut config.pragma_settings;
+        pragmas.remove("journal_mode");
+        pragmas.remove("page_size");
+        pragmas.insert("synchronous".to_string(), self.synchronous.to_string());
+        pragmas.insert("cache_size".to_string(), self.cache_size.to_string());
+
+        ic_rusqlite::set_connection_config(config.clone());
+        ic_rusqlite::close_connection();
+
+        with_connection(|conn| {
+            conn.pragma_update(None, "page_size", self.page_size)
+                .unwrap();
+            conn.pragma_update(None, "journal_mode", self.journal_mode)
+                .unwrap();
+        });
+
+        // a reopened connection keeps the journal mode, the page size of an existing database does not change
+        let pragmas = &mut config.pragma_settings;
+        pragmas.insert("journal_mode".to_string(), self.journal_mode.to_string());
+        pragmas.insert("page_size".to_string(), self.page_size.to_string());
+
+        ic_rusqlite::set_connection_config(config);
+    }
+
+    /// Read back the settings of the open connection.
+    ///
+    /// SQLite silently keeps the previous value for unsupported settings, so the effective values may differ.
+    pub fn effective() -> PragmaConfig {
+        with_connection(|conn| {
+            let journal_mode: String = conn
+                .pragma_query_value(None, "journal_mode", |row| row.get(0))
+                .unwrap();
+
+            let synchronous: i64 = conn
+                .pragma_query_value(None, "synchronous", |row| row.get(0))
+                .unwrap();
+
+            let page_size: u32 = conn
+                .pragma_query_value(None, "page_size", |row| row.get(0))
+                .unwrap();
+
+            let cache_size: i64 = conn
+                .pragma_query_value(None, "cache_size", |row| row.get(0))
+                .unwrap();
+
+            PragmaConfig {
+                journal_mode: JOURNAL_MODES
+                    .iter()
+                    .find(|m| m.eq_ignore_ascii_case(&journal_mode))
+                    .copied()
+                    .unwrap_or("UNKNOWN"),
+                synchronous: SYNCHRONOUS
+                    .get(synchronous as usize)
+                    .copied()
+                    .unwrap_or("UNKNOWN"),
+                page_size,
+                cache_size,
+            }
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "journal_mode={} synchronous={} page_size={} cache_size={}",
+            self.journal_mode, self.synchronous, self.page_size, self.cache_size
+        )
+    }
+}
+
+/// Journal modes known to SQLite.
+const JOURNAL_MODES: &[&str] = &["DELETE", "PERSIST", "TRUNCATE", "MEMORY", "OFF", "WAL"];
+
+/// Synchronous levels, indexed by their numeric value.
+const SYNCHRONOUS: &[&str] = &["OFF", "NORMAL", "FULL", "EXTRA"];
+
+/// Generates one benchmark per combination of the listed pragma values.
+///
+/// The benchmark name is built from the values, e.g. `bench_pragma_memory_full_4096_500000`.
+macro_rules! pragma_matrix {
+    (
+        journal_mode: $journal:tt,
+        synchronous: $sync:tt,
+        page_size: $page:tt,
+        cache_size: $cache:tt $(,)?
+    ) => {
+        pragma_matrix!(@journal $journal $sync $page $cache);
+    };
+
+    (@journal [$($j:ident),*] $sync:tt $page:tt $cache:tt) => {
+        $( pragma_matrix!(@sync $j $sync $page $cache); )*
+    };
+
+    (@sync $j:ident [$($s:ident),*] $page:tt $cache:tt) => {
+        $( pragma_matrix!(@page $j $s $page $cache); )*
+    };
+
+    (@page $j:ident $s:ident [$($p:literal),*] $cache:tt) => {
+        $( pragma_matrix!(@cache $j $s $p $cache); )*
+    };
+
+    (@cache $j:ident $s:ident $p:literal [$($c:literal),*]) => {
+        $(
+            paste::paste! {
+                #[bench(raw)]
+                fn [<bench_pragma_ $j:lower _ $s:lower _ $p _ $c>]() -> BenchResult {
+                    bench_pragma(PragmaConfig {
+                        journal_mode: stringify!($j),
+                        synchronous: stringify!($s),
+                        page_size: $p,
+                        cache_size: $c,
+                    })
+                }
+            }
+        )*
+    };
+}
+
+mod benches {
+    use super::*;
+    use crate::{add_users_sqlite, create_tables, init_payload};
+    use canbench_rs::{bench, bench_fn, bench_scope, BenchResult};
+
+    // initial count of inserted elements
+    const INITIAL_COUNT: u64 = 1000000u64;
+    // do insertions in the middle of the existing element set
+    const OFFSET: u64 = INITIAL_COUNT / 2 + 5;
+    // size of an element being inserted
+    const PAYLOAD_SIZE: usize = 100usize;
+
+    fn bench_pragma(config: PragmaConfig) -> BenchResult {
+        config.apply();
+
+        assert_eq!(PragmaConfig::effective().name(), config.name());
+
+        init_payload(PAYLOAD_SIZE);
+        create_tables();
+
+        add_users_sqlite(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            {
+                let _p = bench_scope("add_00001");
+                add_users_sqlite(OFFSET, 10, 1);
+            }
+            {
+                let _p = bench_scope("add_00100");
+                add_users_sqlite(OFFSET + 1, 10, 100);
+            }
+            {
+                let _p = bench_scope("add_10000");
+                add_users_sqlite(OFFSET + 2, 10, 10000);
+            }
+        })
+    }
+
+    // no `WAL`: it needs shared memory, which the file system of the canister does not provide
+    pragma_matrix! {
+        journal_mode: [DELETE, PERSIST, TRUNCATE, MEMORY, OFF],
+        synchronous: [OFF, FULL],
+        page_size: [4096, 16384],
+        cache_size: [500000],
+    }
+}
@@ -0,0 +1,197 @@
+use std::cell::Cell;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::DefaultMemoryImpl;
+use ic_stable_structures::Memory;
+
+use crate::MEMORY_MANAGER;
+
+const PROFILING: MemoryId = MemoryId::new(50);
+
+// total pages reserved for profiling
+const PROFILING_PAGES: u64 = 4096;
+
+// The memory is the first one to grow on init, so its first pages are the stable pages right after the memory manager header.
+// ic-repl writes the flamegraph traces there (see `rs_config` in `perf/perf.repl`), the ring buffer starts after them.
+const TRACING_PAGES: u64 = 1128;
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+const RING_START: u64 = TRACING_PAGES * WASM_PAGE_SIZE;
+
+// magic + number of records written so far
+const HEADER_SIZE: u64 = 8 + 8;
+
+const MAGIC: &[u8; 8] = b"PROFILE1";
+
+// method + time + count + instructions + heap_increase + stable_memory_increase
+const RECORD_SIZE: u64 = 6 * 8;
+
+const CAPACITY: u64 =
+    ((PROFILING_PAGES - TRACING_PAGES) * WASM_PAGE_SIZE - HEADER_SIZE) / RECORD_SIZE;
+
+thread_local! {
+    // off until `profiling_enable` is called, so the benches measure the methods without the recording
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Profiled canister methods.
+#[derive(Clone, Copy, Debug)]
+#[repr(u64)]
+pub enum Method {
+    AddUsersBtree = 1,
+    AddUsersSqlite = 2,
+}
+
+impl Method {
+    fn name(code: u64) -> String {
+        match code {
+            1 => "add_users_btree".to_string(),
+            2 => "add_users_sqlite".to_string(),
+            _ => format!("unknown({code})"),
+        }
+    }
+}
+
+/// Per-call measurements of a profiled method.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CallProfile {
+    /// Sequential number of the call since the profiling was initialized
+    pub seq: u64,
+    pub method: String,
+    /// Call time in nanoseconds since the epoch
+    pub time: u64,
+    /// The `count` argument of the call
+    pub count: u64,
+    pub instructions: u64,
+    /// Heap growth in bytes
+    pub heap_increase: u64,
+    /// Stable memory growth in pages
+    pub stable_memory_increase: u64,
+}
+
+fn memory() -> VirtualMemory<DefaultMemoryImpl> {
+    MEMORY_MANAGER.with(|m| m.borrow().get(PROFILING))
+}
+
+fn read_u64(memory: &VirtualMemory<DefaultMemoryImpl>, offset: u64) -> u64 {
+    let mut buf = [0u8; 8];
+    memory.read(offset, &mut buf);
+    u64::from_le_bytes(buf)
+}
+
+fn is_initialized(memory: &VirtualMemory<DefaultMemoryImpl>) -> bool {
+    if memory.size() < PROFILING_PAGES {
+        return false;
+    }
+
+    let mut magic = [0u8; 8];
+    memory.read(RING_START, &mut magic);
+
+    &magic == MAGIC
+}
+
+fn record_offset(seq: u64) -> u64 {
+    RING_START + HEADER_SIZE + (seq % CAPACITY) * RECORD_SIZE
+}
+
+/// Reserve the profiling memory and reset the ring buffer.
+pub fn profiling_init() {
+    let memory = memory();
+
+    if memory.size() < PROFILING_PAGES {
+        memory.grow(PROFILING_PAGES - memory.size());
+    }
+
+    memory.write(RING_START, MAGIC);
+    memory.write(RING_START + 8, &0u64.to_le_bytes());
+}
+
+fn heap_size() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+/// Turn the recording of the profiled calls on or off, it is off after install and upgrade.
+#[ic_cdk::update]
+fn profiling_enable(enabled: bool) {
+    RECORDING.set(enabled);
+}
+
+/// Run the method body and store its measurements in the ring buffer.
+///
+/// Calls are only recorded after `profiling_enable(true)` and if the profiling memory was initialized.
+pub fn profile<R>(method: Method, count: u64, f: impl FnOnce() -> R) -> R {
+    if !RECORDING.get() {
+        return f();
+    }
+
+    let start_heap = heap_size();
+    let start_stable_memory = ic_cdk::api::stable_size();
+    let start_instructions = ic_cdk::api::instruction_counter();
+
+    let result = f();
+
+    let instructions = ic_cdk::api::instruction_counter() - start_instructions;
+    let stable_memory_increase = ic_cdk::api::stable_size() - start_stable_memory;
+    let heap_increase = heap_size() - start_heap;
+
+    let memory = memory();
+
+    if is_initialized(&memory) {
+        let seq = read_u64(&memory, RING_START + 8);
+
+        let mut buf = Vec::with_capacity(RECORD_SIZE as usize);
+        for value in [
+            method as u64,
+            ic_cdk::api::time(),
+            count,
+            instructions,
+            heap_increase,
+            stable_memory_increase,
+        ] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        memory.write(record_offset(seq), &buf);
+        memory.write(RING_START + 8, &(seq + 1).to_le_bytes());
+    }
+
+    result
+}
+
+/// Return up to `limit` recorded calls starting from the sequential number `from`.
+///
+/// Calls that were already overwritten in the ring buffer are skipped.
+#[ic_cdk::query]
+fn profiling_dump(from: u64, limit: u64) -> Vec<CallProfile> {
+    let memory = memory();
+
+    if !is_initialized(&memory) {
+        return Vec::new();
+    }
+
+    let written = read_u64(&memory, RING_START + 8);
+    let first = from.max(written.saturating_sub(CAPACITY));
+    let last = written.min(first.saturating_add(limit));
+
+    (first..last)
+        .map(|seq| {
+            let offset = record_offset(seq);
+            let field = |idx: u64| read_u64(&memory, offset + idx * 8);
+
+            CallProfile {
+                seq,
+                method: Method::name(field(0)),
+                time: field(1),
+                count: field(2),
+                instructions: field(3),
+                heap_increase: field(4),
+                stable_memory_increase: field(5),
+            }
+        })
+        .collect()
+}
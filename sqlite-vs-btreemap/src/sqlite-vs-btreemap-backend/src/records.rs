@@ -0,0 +1,451 @@
+use std::borrow::Cow;
+
+use candid::CandidType;
+use candid::Deserialize;
+
+use ic_rusqlite::with_connection;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::memory_manager::VirtualMemory;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::BTreeMap;
+use ic_stable_structures::DefaultMemoryImpl;
+use ic_stable_structures::Storable;
+
+use std::cell::RefCell;
+
+use crate::for_each_user;
+use crate::MEMORY_MANAGER;
+
+// maximum length of the text fields, so the bounded variants can declare their size
+const MAX_TEXT_LEN: usize = 64;
+
+// id + age + balance + created_at
+const BINARY_HEADER_SIZE: usize = 8 + 4 + 8 + 8;
+
+// header + two length-prefixed text fields
+const MAX_BINARY_SIZE: usize = BINARY_HEADER_SIZE + 2 * (2 + MAX_TEXT_LEN);
+
+// Candid adds the "DIDL" magic and the type table on top of the field values
+const MAX_CANDID_SIZE: usize = 256;
+
+// MemoryId 0 holds the `String` map of lib.rs
+const CANDID_RECORDS: MemoryId = MemoryId::new(1);
+const BOUNDED_CANDID_RECORDS: MemoryId = MemoryId::new(2);
+const BINARY_RECORDS: MemoryId = MemoryId::new(3);
+const BOUNDED_BINARY_RECORDS: MemoryId = MemoryId::new(4);
+
+/// A multi-field user record, stored both in the stable structures and in the `user_records` table.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct UserRecord {
+    pub id: u64,
+    pub username: String,
+    pub email: String,
+    pub age: u32,
+    pub balance: f64,
+    pub created_at: u64,
+}
+
+impl UserRecord {
+    /// Generate a deterministic record for the given id.
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            username: format!("user{id}"),
+            email: format!("user{id}@example.com"),
+            age: (id % 80 + 18) as u32,
+            balance: id as f64 * 1.25,
+            created_at: 1_700_000_000 + id,
+        }
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(BINARY_HEADER_SIZE + 4 + self.username.len() + self.email.len());
+
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&self.age.to_le_bytes());
+        buf.extend_from_slice(&self.balance.to_le_bytes());
+        buf.extend_from_slice(&self.created_at.to_le_bytes());
+
+        write_text(&mut buf, &self.username);
+        write_text(&mut buf, &self.email);
+
+        buf
+    }
+
+    fn from_binary(bytes: &[u8]) -> Self {
+        let id = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let age = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let balance = f64::from_le_bytes(bytes[12..20].try_into().unwrap());
+        let created_at = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+
+        let mut pos = BINARY_HEADER_SIZE;
+        let username = read_text(bytes, &mut pos);
+        let email = read_text(bytes, &mut pos);
+
+        Self {
+            id,
+            username,
+            email,
+            age,
+            balance,
+            created_at,
+        }
+    }
+}
+
+fn write_text(buf: &mut Vec<u8>, text: &str) {
+    assert!(text.len() <= MAX_TEXT_LEN, "text field is too long: {text}");
+
+    buf.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    buf.extend_from_slice(text.as_bytes());
+}
+
+fn read_text(bytes: &[u8], pos: &mut usize) -> String {
+    let len = u16::from_le_bytes(bytes[*pos..*pos + 2].try_into().unwrap()) as usize;
+    *pos += 2;
+
+    let text = std::str::from_utf8(&bytes[*pos..*pos + len])
+        .expect("invalid utf-8 in a stored record")
+        .to_string();
+    *pos += len;
+
+    text
+}
+
+fn to_candid(record: &UserRecord) -> Vec<u8> {
+    candid::encode_one(record).unwrap()
+}
+
+fn from_candid(bytes: &[u8]) -> UserRecord {
+    candid::decode_one(bytes).unwrap()
+}
+
+/// Declare a `UserRecord` wrapper stored with the given encoding and bound.
+macro_rules! storable_record {
+    ($(#[$doc:meta])* $name:ident, $encode:path, $decode:path, $bound:expr) => {
+        $(#[$doc])*
+        pub struct $name(pub UserRecord);
+
+        impl Storable for $name {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned($encode(&self.0))
+            }
+
+            fn into_bytes(self) -> Vec<u8> {
+                $encode(&self.0)
+            }
+
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                Self($decode(&bytes))
+            }
+
+            const BOUND: Bound = $bound;
+        }
+    };
+}
+
+storable_record!(
+    /// Candid-encoded record without size bounds.
+    CandidRecord,
+    to_candid,
+    from_candid,
+    Bound::Unbounded
+);
+
+storable_record!(
+    /// Candid-encoded record with declared maximum size.
+    BoundedCandidRecord,
+    to_candid,
+    from_candid,
+    Bound::Bounded {
+        max_size: MAX_CANDID_SIZE as u32,
+        is_fixed_size: false,
+    }
+);
+
+storable_record!(
+    /// Hand-rolled binary record without size bounds.
+    BinaryRecord,
+    UserRecord::to_binary,
+    UserRecord::from_binary,
+    Bound::Unbounded
+);
+
+storable_record!(
+    /// Hand-rolled binary record with declared maximum size.
+    BoundedBinaryRecord,
+    UserRecord::to_binary,
+    UserRecord::from_binary,
+    Bound::Bounded {
+        max_size: MAX_BINARY_SIZE as u32,
+        is_fixed_size: false,
+    }
+);
+
+type Map<V> = BTreeMap<u64, V, VirtualMemory<DefaultMemoryImpl>>;
+
+thread_local! {
+    static CANDID_MAP: RefCell<Map<CandidRecord>> = RefCell::new(
+        BTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CANDID_RECORDS)))
+    );
+
+    static BOUNDED_CANDID_MAP: RefCell<Map<BoundedCandidRecord>> = RefCell::new(
+        BTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BOUNDED_CANDID_RECORDS)))
+    );
+
+    static BINARY_MAP: RefCell<Map<BinaryRecord>> = RefCell::new(
+        BTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BINARY_RECORDS)))
+    );
+
+    static BOUNDED_BINARY_MAP: RefCell<Map<BoundedBinaryRecord>> = RefCell::new(
+        BTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BOUNDED_BINARY_RECORDS)))
+    );
+}
+
+fn add_records<V: Storable>(
+    map: &mut Map<V>,
+    offset: u64,
+    increment: u64,
+    count: u64,
+    wrap: impl Fn(UserRecord) -> V,
+) {
+    for_each_user(offset, increment, count, |id| {
+        map.insert(id, wrap(UserRecord::new(id)));
+    });
+}
+
+#[ic_cdk::update]
+fn create_record_tables() {
+    with_connection(|conn| {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_records (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL,
+                email TEXT NOT NULL,
+                age INTEGER NOT NULL,
+                balance REAL NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            (),
+        )
+        .unwrap();
+    });
+}
+
+#[ic_cdk::update]
+fn add_records_candid(offset: u64, increment: u64, count: u64) {
+    CANDID_MAP.with_borrow_mut(|map| add_records(map, offset, increment, count, CandidRecord));
+}
+
+#[ic_cdk::update]
+fn add_records_candid_bounded(offset: u64, increment: u64, count: u64) {
+    BOUNDED_CANDID_MAP
+        .with_borrow_mut(|map| add_records(map, offset, increment, count, BoundedCandidRecord));
+}
+
+#[ic_cdk::update]
+fn add_records_binary(offset: u64, increment: u64, count: u64) {
+    BINARY_MAP.with_borrow_mut(|map| add_records(map, offset, increment, count, BinaryRecord));
+}
+
+#[ic_cdk::update]
+fn add_records_binary_bounded(offset: u64, increment: u64, count: u64) {
+    BOUNDED_BINARY_MAP
+        .with_borrow_mut(|map| add_records(map, offset, increment, count, BoundedBinaryRecord));
+}
+
+#[ic_cdk::update]
+fn add_records_sqlite(offset: u64, increment: u64, count: u64) {
+    with_connection(|mut conn| {
+        let tx = conn.transaction().unwrap();
+
+        {
+            let mut stmt = tx
+                .prepare_cached(
+                    "insert into user_records (id, username, email, age, balance, created_at) values (?, ?, ?, ?, ?, ?)",
+                )
+                .unwrap();
+
+            for_each_user(offset, increment, count, |id| {
+                let r = UserRecord::new(id);
+                stmt.execute(ic_rusqlite::params![
+                    r.id,
+                    r.username,
+                    r.email,
+                    r.age,
+                    r.balance,
+                    r.created_at
+                ])
+                .expect("insert of a record failed!");
+            });
+        }
+
+        tx.commit().expect("COMMIT RECORD INSERTION FAILED!");
+    })
+}
+
+fn read_record_sqlite(conn: &ic_rusqlite::Connection, id: u64) -> UserRecord {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT id, username, email, age, balance, created_at FROM user_records WHERE id = ?1",
+        )
+        .unwrap();
+
+    stmt.query_row((&id,), |row| {
+        Ok(UserRecord {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            email: row.get(2)?,
+            age: row.get(3)?,
+            balance: row.get(4)?,
+            created_at: row.get(5)?,
+        })
+    })
+    .unwrap()
+}
+
+mod benches {
+    use super::*;
+    use canbench_rs::{bench, bench_fn, BenchResult};
+
+    // initial count of inserted elements
+    const INITIAL_COUNT: u64 = 1000000u64;
+    // do insertions in the middle of the existing element set
+    const OFFSET: u64 = INITIAL_COUNT / 2 + 5;
+    // number of records to insert or to read
+    const COUNT: u64 = 100u64;
+
+    #[bench(raw)]
+    fn bench_records_candid_add_00100() -> BenchResult {
+        add_records_candid(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            add_records_candid(OFFSET, 10, COUNT);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_candid_bounded_add_00100() -> BenchResult {
+        add_records_candid_bounded(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            add_records_candid_bounded(OFFSET, 10, COUNT);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_binary_add_00100() -> BenchResult {
+        add_records_binary(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            add_records_binary(OFFSET, 10, COUNT);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_binary_bounded_add_00100() -> BenchResult {
+        add_records_binary_bounded(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            add_records_binary_bounded(OFFSET, 10, COUNT);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_sqlite_add_00100() -> BenchResult {
+        create_record_tables();
+        add_records_sqlite(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            add_records_sqlite(OFFSET, 10, COUNT);
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_candid_read_00100() -> BenchResult {
+        add_records_candid(OFFSET, 10, COUNT);
+        add_records_candid(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            CANDID_MAP.with_borrow(|map| {
+                for_each_user(OFFSET, 10, COUNT, |id| {
+                    let _ = map.get(&id).unwrap();
+                });
+            });
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_candid_bounded_read_00100() -> BenchResult {
+        add_records_candid_bounded(OFFSET, 10, COUNT);
+        add_records_candid_bounded(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            BOUNDED_CANDID_MAP.with_borrow(|map| {
+                for_each_user(OFFSET, 10, COUNT, |id| {
+                    let _ = map.get(&id).unwrap();
+                });
+            });
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_binary_read_00100() -> BenchResult {
+        add_records_binary(OFFSET, 10, COUNT);
+        add_records_binary(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            BINARY_MAP.with_borrow(|map| {
+                for_each_user(OFFSET, 10, COUNT, |id| {
+                    let _ = map.get(&id).unwrap();
+                });
+            });
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_binary_bounded_read_00100() -> BenchResult {
+        add_records_binary_bounded(OFFSET, 10, COUNT);
+        add_records_binary_bounded(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            BOUNDED_BINARY_MAP.with_borrow(|map| {
+                for_each_user(OFFSET, 10, COUNT, |id| {
+                    let _ = map.get(&id).unwrap();
+                });
+            });
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_sqlite_read_00100() -> BenchResult {
+        create_record_tables();
+        add_records_sqlite(OFFSET, 10, COUNT);
+        add_records_sqlite(0, 10, INITIAL_COUNT);
+
+        bench_fn(|| {
+            with_connection(|conn| {
+                for_each_user(OFFSET, 10, COUNT, |id| {
+                    let _ = read_record_sqlite(&conn, id);
+                });
+            });
+        })
+    }
+
+    #[bench(raw)]
+    fn bench_records_roundtrip() -> BenchResult {
+        let record = UserRecord::new(OFFSET);
+
+        bench_fn(|| {
+            assert_eq!(
+                CandidRecord::from_bytes(CandidRecord(record.clone()).to_bytes()).0,
+                record
+            );
+            assert_eq!(
+                BinaryRecord::from_bytes(BinaryRecord(record.clone()).to_bytes()).0,
+                record
+            );
+        })
+    }
+}